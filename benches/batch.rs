@@ -0,0 +1,98 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use flat_spatial::batch::BatchResults;
+use flat_spatial::shape::BoundingBox;
+use flat_spatial::{AABBGrid, Grid};
+
+const SIDE: f32 = 3000.0;
+
+fn random_points(rng: &fastrand::Rng, n: usize) -> Vec<[f32; 2]> {
+    (0..n)
+        .map(|_| [rng.f32() * SIDE, rng.f32() * SIDE])
+        .collect()
+}
+
+/// Sorts the points row by row, like queries coming from a spatially coherent source
+fn sort_rows(points: &mut [[f32; 2]]) {
+    points.sort_by_key(|p| ((p[1] / 20.0) as i32, (p[0] / 20.0) as i32));
+}
+
+fn grid_batch(c: &mut Criterion) {
+    let rng = fastrand::Rng::with_seed(1);
+    let mut g: Grid<(), [f32; 2]> = Grid::new(10);
+    for p in random_points(&rng, 500_000) {
+        g.insert(p, ());
+    }
+
+    let mut centers = random_points(&rng, 200_000);
+    let mut group = c.benchmark_group("grid query_around 500k points 200k queries");
+    group.sample_size(10);
+
+    for &sorted in &[false, true] {
+        if sorted {
+            sort_rows(&mut centers);
+        }
+        let queries: Vec<_> = centers.iter().map(|&p| (p, 10.0)).collect();
+        let name = if sorted { "sorted" } else { "random" };
+
+        group.bench_function(format!("loop {}", name), |b| {
+            let mut offsets = vec![];
+            let mut handles = vec![];
+            b.iter(|| {
+                offsets.clear();
+                handles.clear();
+                for &(pos, radius) in &queries {
+                    handles.extend(g.query_around(pos, radius).map(|(h, _)| h));
+                    offsets.push(handles.len());
+                }
+                handles.len()
+            })
+        });
+
+        group.bench_function(format!("batch {}", name), |b| {
+            let mut out = BatchResults::new();
+            b.iter(|| {
+                g.query_around_batch(&queries, &mut out);
+                out.handles().len()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn aabbgrid_batch(c: &mut Criterion) {
+    let rng = fastrand::Rng::with_seed(2);
+    let mut g: AABBGrid<(), BoundingBox<[f32; 2]>> = AABBGrid::new(10);
+    for p in random_points(&rng, 200_000) {
+        g.insert(BoundingBox::new(p, [p[0] + 3.0, p[1] + 3.0]), ());
+    }
+
+    let queries: Vec<_> = random_points(&rng, 200_000)
+        .into_iter()
+        .map(|p| BoundingBox::new(p, [p[0] + 15.0, p[1] + 15.0]))
+        .collect();
+    let mut group = c.benchmark_group("aabbgrid query 200k boxes 200k queries");
+    group.sample_size(10);
+
+    group.bench_function("loop random", |b| {
+        let mut handles = vec![];
+        b.iter(|| {
+            handles.clear();
+            for &q in &queries {
+                handles.extend(g.query(q).map(|(h, _, _)| h));
+            }
+            handles.len()
+        })
+    });
+
+    group.bench_function("batch random", |b| {
+        let mut out = BatchResults::new();
+        b.iter(|| {
+            g.query_batch(&queries, &mut out);
+            out.handles().len()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, grid_batch, aabbgrid_batch);
+criterion_main!(benches);
@@ -1,7 +1,16 @@
+use crate::batch::BatchResults;
 use crate::cell::AABBGridCell;
-use crate::storage::{cell_range, SparseStorage};
-use crate::AABB;
+use crate::heatmap::Heatmap;
+use crate::shape::{PolygonQuery, Shape};
+use crate::stats::GridStats;
+use crate::storage::{
+    cell_count, cell_range, first_shared_cell, polygon_rows, suggest_cell_size, CellIdx,
+    GroupByCell, SparseStorage,
+};
+use crate::validate::{ValidationError, MAX_ERRORS};
+use crate::{Vec2, AABB};
 use slotmapd::{new_key_type, SlotMap};
+use std::mem::size_of;
 
 pub type AABBGridObjects<O, AB> = SlotMap<AABBGridHandle, StoreObject<O, AB>>;
 
@@ -11,6 +20,20 @@ new_key_type! {
     pub struct AABBGridHandle;
 }
 
+/// State of an object, maintain() updates the internals of the grid and resets this to Unchanged
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ObjectState<AB: AABB> {
+    Unchanged,
+    NewAABB(AB),
+    Removed,
+}
+
+#[cfg(feature = "serde")]
+fn unchanged<AB: AABB>() -> ObjectState<AB> {
+    ObjectState::Unchanged
+}
+
 /// The actual object stored in the store
 #[derive(Clone, Copy)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
@@ -18,6 +41,31 @@ pub struct StoreObject<O: Copy, AB: AABB> {
     /// User-defined object to be associated with a value
     pub obj: O,
     pub aabb: AB,
+    /// Missing from grids serialized before lazy updates existed
+    #[cfg_attr(feature = "serde", serde(default = "unchanged"))]
+    pub(crate) state: ObjectState<AB>,
+}
+
+/// How the objects are compared to the query by `query_mode`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueryMode {
+    /// Objects intersecting the query, like `query`
+    Intersects,
+    /// Objects entirely inside the query (with the borders included), such as a marquee selection
+    ContainedBy,
+    /// Objects entirely containing the query (with the borders included)
+    Contains,
+}
+
+/// A hit of `AABBGrid::sweep`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SweepHit<V2: Vec2> {
+    pub handle: AABBGridHandle,
+    /// Fraction of the velocity at which the boxes start touching, between 0 and 1
+    pub toi: f32,
+    /// Normal of the face of the hit box that is touched, pointing towards the moving box.
+    /// It is zero if the boxes already intersect at the start.
+    pub normal: V2,
 }
 
 /// `AABBGrid` is a generic aabb-based spatial partitioning structure that uses a generic storage of cells which acts as a
@@ -35,6 +83,10 @@ pub struct StoreObject<O: Copy, AB: AABB> {
 ///
 /// Use this grid for mostly static objects with the occasional removal/position update if needed.
 ///
+/// If many objects move every frame, `set_aabb_lazy` and `remove_lazy` can be used instead.
+/// They only mark the objects as dirty, and `maintain` then applies all of them in one pass,
+/// much like `Grid::set_position`.
+///
 /// A `SlotMap` is used for objects managing, adding a level of indirection between aabbs and objects.
 /// `SlotMap` is used because removal doesn't alter handles given to the user, while still having constant time access.
 /// However it requires O to be copy, but `SlotMap's` author stated that they were working on a similar
@@ -55,13 +107,62 @@ pub struct StoreObject<O: Copy, AB: AABB> {
 /// let handle = g.insert(Rect::new([0.0, 0.0].into(), [10.0, 10.0].into()), ());
 /// // Use handle however you want
 /// ```
+///
+/// ## Shapes
+/// The `AABB::intersects` test is the narrow phase of `query`. The shapes of the `shape` module
+/// (circles, capsules, oriented boxes and convex polygons) implement it exactly, so an `AABBGrid` of shapes
+/// returns only the shapes that really intersect, and `query_shape` can query it with any other kind of shape.
+///
+/// ## Serialization
+/// With the `serde` feature, deserialized grids are checked with `validate` so that corrupted data is
+/// rejected with an error instead of leading to undefined behavior in queries.
 #[derive(Clone)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(
+        try_from = "AABBGridRaw<O, AB>",
+        bound(deserialize = "O: serde::Deserialize<'de>, AB: serde::Deserialize<'de>")
+    )
+)]
 pub struct AABBGrid<O: Copy, AB: AABB> {
+    storage: SparseStorage<AABBGridCell>,
+    pub(crate) objects: AABBGridObjects<O, AB>,
+    // Cache maintain vec to avoid allocating every time maintain is called
+    #[cfg_attr(feature = "serde", serde(skip))]
+    to_relocate: Vec<AABBGridHandle>,
+}
+
+/// The unchecked serialized form of a grid, turned into an AABBGrid by validating it
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(rename = "AABBGrid")]
+struct AABBGridRaw<O: Copy, AB: AABB> {
     storage: SparseStorage<AABBGridCell>,
     objects: AABBGridObjects<O, AB>,
 }
 
+#[cfg(feature = "serde")]
+impl<O: Copy, AB: AABB> TryFrom<AABBGridRaw<O, AB>> for AABBGrid<O, AB> {
+    type Error = crate::validate::CorruptedGrid<AABBGridHandle>;
+
+    fn try_from(raw: AABBGridRaw<O, AB>) -> Result<Self, Self::Error> {
+        if raw.storage.cell_size <= 0 {
+            return Err(Self::Error::InvalidCellSize(raw.storage.cell_size));
+        }
+
+        // to_relocate is only a cache and is always empty outside of maintain
+        let grid = Self {
+            storage: raw.storage,
+            objects: raw.objects,
+            to_relocate: vec![],
+        };
+        grid.validate().map_err(Self::Error::Invalid)?;
+
+        Ok(grid)
+    }
+}
+
 impl<O: Copy, AB: AABB> AABBGrid<O, AB> {
     /// Creates an empty grid.
     /// The cell size should be about the same magnitude as your queries size.
@@ -69,12 +170,24 @@ impl<O: Copy, AB: AABB> AABBGrid<O, AB> {
         Self {
             storage: SparseStorage::new(cell_size),
             objects: AABBGridObjects::default(),
+            to_relocate: vec![],
+        }
+    }
+
+    /// Creates an empty grid with room for `capacity` objects without reallocating.
+    /// The cell size should be about the same magnitude as your queries size.
+    pub fn with_capacity(cell_size: i32, capacity: usize) -> Self {
+        Self {
+            storage: SparseStorage::new(cell_size),
+            objects: AABBGridObjects::with_capacity_and_key(capacity),
+            to_relocate: vec![],
         }
     }
 
     /// Clears the grid.
     pub fn clear(&mut self) -> impl Iterator<Item = (AB, O)> {
         self.storage = SparseStorage::new(self.storage.cell_size());
+        self.to_relocate.clear();
         let objs = std::mem::take(&mut self.objects);
         objs.into_iter().map(|(_, o)| (o.aabb, o.obj))
     }
@@ -86,20 +199,154 @@ impl<O: Copy, AB: AABB> AABBGrid<O, AB> {
             storage, objects, ..
         } = self;
 
-        let h = objects.insert(StoreObject { obj, aabb });
+        let h = objects.insert(StoreObject {
+            obj,
+            aabb,
+            state: ObjectState::Unchanged,
+        });
         cells_apply(storage, &aabb, |cell, sing_cell| {
             cell.objs.push((h, sing_cell));
         });
         h
     }
 
-    /// Updates the aabb of an object.
+    /// Inserts many objects at once, much faster than calling insert for each of them.
+    /// The objects are counted per cell first so that each cell is allocated only once.
+    /// Returns the handles of the inserted objects, in the same order as the input.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::AABBGrid;
+    /// use euclid::default::Rect;
+    ///
+    /// let mut g: AABBGrid<i32, Rect<f32>> = AABBGrid::new(10);
+    /// let handles = g.extend(vec![
+    ///     (Rect::new([0.0, 0.0].into(), [25.0, 5.0].into()), 1),
+    ///     (Rect::new([3.0, 3.0].into(), [1.0, 1.0].into()), 2),
+    /// ]);
+    /// assert_eq!(g.get(handles[1]).unwrap().obj, 2);
+    /// ```
+    pub fn extend(&mut self, objs: impl IntoIterator<Item = (AB, O)>) -> Vec<AABBGridHandle> {
+        let Self {
+            storage, objects, ..
+        } = self;
+
+        let objs = objs.into_iter();
+        let mut handles = Vec::with_capacity(objs.size_hint().0);
+        let mut by_cell = Vec::with_capacity(objs.size_hint().0);
+
+        objects.reserve(objs.size_hint().0);
+        for (aabb, obj) in objs {
+            let h = objects.insert(StoreObject {
+                obj,
+                aabb,
+                state: ObjectState::Unchanged,
+            });
+            push_cells(storage, &aabb, h, &mut by_cell);
+            handles.push(h);
+        }
+
+        fill_cells(storage, by_cell);
+
+        handles
+    }
+
+    /// Rebuilds a grid from its objects, applying any pending aabb update or removal.
+    /// Handles are kept as is.
+    pub(crate) fn from_objects(cell_size: i32, mut objects: AABBGridObjects<O, AB>) -> Self {
+        let mut storage = SparseStorage::new(cell_size);
+
+        objects.retain(|_, obj| !matches!(obj.state, ObjectState::Removed));
+
+        let mut by_cell = Vec::with_capacity(objects.len());
+        for (h, obj) in objects.iter_mut() {
+            if let ObjectState::NewAABB(aabb) = obj.state {
+                obj.aabb = aabb;
+            }
+            obj.state = ObjectState::Unchanged;
+            push_cells(&storage, &obj.aabb, h, &mut by_cell);
+        }
+
+        fill_cells(&mut storage, by_cell);
+
+        Self {
+            storage,
+            objects,
+            to_relocate: vec![],
+        }
+    }
+
+    /// Rebuilds the grid with a new cell size, keeping all the handles.
+    /// Pending lazy aabb updates and removals are applied.
+    pub fn rebuild(&mut self, cell_size: i32) {
+        let objects = std::mem::take(&mut self.objects);
+        *self = Self::from_objects(cell_size, objects);
+    }
+
+    /// Suggests a cell size for this grid, aiming for about 16 objects per non-empty cell
+    /// (objects spanning many cells are counted once per cell).
+    /// `query_extents` is an optional sample of the widths of the queries that are run on this grid,
+    /// pass an empty slice if you don't have one.
+    /// The result can then be given to `rebuild`.
+    pub fn suggest_cell_size(&self, query_extents: &[f32]) -> i32 {
+        suggest_cell_size(
+            self.storage.cell_size(),
+            self.storage.cells.len(),
+            self.storage.cells.values().map(|x| x.objs.len()).sum(),
+            query_extents,
+        )
+    }
+
+    /// Returns occupancy statistics about the grid, to check if the cell size is adapted to the problem.
+    pub fn stats(&self) -> GridStats {
+        let storage = &self.storage;
+        let cells = &storage.cells;
+        let mut stats = GridStats::from_cells(cells.values().map(|x| (x.objs.len(), x.dirty)));
+
+        stats.objects = self.objects.len();
+        if stats.objects > 0 {
+            let multi_cell = self
+                .objects
+                .values()
+                .filter(|x| storage.cell_id(x.aabb.ll()) != storage.cell_id(x.aabb.ur()))
+                .count();
+            stats.multi_cell_ratio = multi_cell as f32 / stats.objects as f32;
+        }
+        stats.memory_bytes = size_of::<Self>()
+            + cells.capacity() * size_of::<(CellIdx, AABBGridCell)>()
+            + cells
+                .values()
+                .map(|x| x.objs.capacity() * size_of::<(AABBGridHandle, bool)>())
+                .sum::<usize>()
+            + self.objects.capacity() * (size_of::<StoreObject<O, AB>>() + size_of::<u32>())
+            + self.to_relocate.capacity() * size_of::<AABBGridHandle>();
+
+        stats
+    }
+
+    /// Rasterizes the number of objects per cell over the rectangle covered by the given aabb, one pixel per cell.
+    /// Objects spanning multiple cells are counted in each of them. See `Heatmap` to export it as an image.
+    pub fn heatmap(&self, aabb: AB) -> Heatmap {
+        let ll_id = self.storage.cell_id(aabb.ll());
+        let ur_id = self.storage.cell_id(aabb.ur());
+
+        self.storage
+            .rasterize(ll_id, ur_id, |cell| cell.objs.len() as u32)
+    }
+
+    /// Updates the aabb of an object (if it is not marked for deletion).
+    /// Any pending lazy update for this object is overridden.
     pub fn set_aabb(&mut self, handle: AABBGridHandle, aabb: AB) {
         let obj = self
             .objects
             .get_mut(handle)
             .expect("Object not in grid anymore");
 
+        if matches!(obj.state, ObjectState::Removed) {
+            return;
+        }
+        obj.state = ObjectState::Unchanged;
+
         let storage = &mut self.storage;
 
         let old_ll = storage.cell_mut(obj.aabb.ll()).0;
@@ -147,6 +394,133 @@ impl<O: Copy, AB: AABB> AABBGrid<O, AB> {
         Some(st.obj)
     }
 
+    /// Lazily sets the aabb of an object (if it is not marked for deletion).
+    /// This won't be taken into account until maintain() is called.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::AABBGrid;
+    /// use euclid::default::Rect;
+    ///
+    /// let mut g: AABBGrid<(), Rect<f32>> = AABBGrid::new(10);
+    /// let h = g.insert(Rect::new([0.0, 0.0].into(), [5.0, 5.0].into()), ());
+    /// g.set_aabb_lazy(h, Rect::new([30.0, 30.0].into(), [5.0, 5.0].into()));
+    /// assert_eq!(g.query(Rect::new([31.0, 31.0].into(), [1.0, 1.0].into())).count(), 0);
+    ///
+    /// g.maintain();
+    /// assert_eq!(g.query(Rect::new([31.0, 31.0].into(), [1.0, 1.0].into())).count(), 1);
+    /// ```
+    pub fn set_aabb_lazy(&mut self, handle: AABBGridHandle, aabb: AB) {
+        let obj = match self.objects.get_mut(handle) {
+            Some(x) => x,
+            None => {
+                debug_assert!(false, "Object not in grid anymore");
+                return;
+            }
+        };
+
+        if matches!(obj.state, ObjectState::Removed) {
+            return;
+        }
+
+        obj.state = ObjectState::NewAABB(aabb);
+        mark_dirty(&mut self.storage, &obj.aabb);
+    }
+
+    /// Lazily removes an object from the grid.
+    /// This won't be taken into account until maintain() is called.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::AABBGrid;
+    /// use euclid::default::Rect;
+    ///
+    /// let mut g: AABBGrid<(), Rect<f32>> = AABBGrid::new(10);
+    /// let h = g.insert(Rect::new([0.0, 0.0].into(), [5.0, 5.0].into()), ());
+    /// g.remove_lazy(h);
+    ///
+    /// assert!(g.get(h).is_some());
+    /// g.maintain();
+    /// assert!(g.get(h).is_none());
+    /// ```
+    pub fn remove_lazy(&mut self, handle: AABBGridHandle) -> Option<O> {
+        let obj = self.objects.get_mut(handle)?;
+
+        obj.state = ObjectState::Removed;
+        mark_dirty(&mut self.storage, &obj.aabb);
+
+        Some(obj.obj)
+    }
+
+    /// Maintains the world, applying all the lazy aabb updates and removals
+    /// and removing empty cells.
+    /// Every cell is looked at, but only the cells covered by the old aabbs of the updated objects
+    /// (which are marked dirty) go through their objects.
+    ///
+    /// If you need maintain to be deterministic (for example, for networked games),
+    /// use maintain_deterministic which sorts the relocations
+    pub fn maintain(&mut self) {
+        self.maintain_cells();
+        self.apply_relocations();
+    }
+
+    /// Same as maintain() but deterministic by sorting the relocations
+    pub fn maintain_deterministic(&mut self) {
+        self.maintain_cells();
+        self.to_relocate.sort_unstable();
+        self.apply_relocations();
+    }
+
+    fn maintain_cells(&mut self) {
+        let Self {
+            storage,
+            objects,
+            to_relocate,
+            ..
+        } = self;
+
+        let cell_size = storage.cell_size();
+        storage.modify_with_id(|id, cell| {
+            cell.maintain(id, cell_size, objects, to_relocate);
+            cell.objs.is_empty()
+        });
+    }
+
+    fn apply_relocations(&mut self) {
+        let Self {
+            storage,
+            objects,
+            to_relocate,
+            ..
+        } = self;
+
+        for handle in to_relocate.drain(..) {
+            let obj = &mut objects[handle];
+            match obj.state {
+                ObjectState::Unchanged => {}
+                ObjectState::NewAABB(aabb) => {
+                    let old_ll = storage.cell_id(obj.aabb.ll());
+                    let old_ur = storage.cell_id(obj.aabb.ur());
+
+                    obj.aabb = aabb;
+                    obj.state = ObjectState::Unchanged;
+
+                    if old_ll == storage.cell_id(aabb.ll()) && old_ur == storage.cell_id(aabb.ur())
+                    {
+                        continue;
+                    }
+
+                    cells_apply(storage, &aabb, |cell, sing_cell| {
+                        cell.objs.push((handle, sing_cell));
+                    });
+                }
+                ObjectState::Removed => {
+                    objects.remove(handle);
+                }
+            }
+        }
+    }
+
     /// Iterate over all handles
     pub fn handles(&self) -> impl Iterator<Item = AABBGridHandle> + '_ {
         self.objects.keys()
@@ -173,16 +547,165 @@ impl<O: Copy, AB: AABB> AABBGrid<O, AB> {
     }
 
     /// Queries for objects intersecting a given AABB.
+    /// See `query_mode` to query the objects contained by or containing the AABB.
     pub fn query(&self, aabb: AB) -> impl Iterator<Item = (AABBGridHandle, &AB, &O)> + '_ {
-        self.query_broad(aabb).filter_map(move |h| {
-            // Safety: All objects in the cells are guaranteed to be valid.
-            let obj = unsafe { self.objects.get_unchecked(h) };
-            if aabb.intersects(&obj.aabb) {
+        self.query_mode(aabb, QueryMode::Intersects)
+    }
+
+    /// Queries for objects that intersect, are contained by, or contain the given AABB depending on the mode.
+    /// `ContainedBy` and `Contains` compare the bounding boxes of the objects, even if `AABB::intersects` is exact.
+    ///
+    /// The objects containing the query are in all the cells the query covers, so `Contains` only visits one cell.
+    /// `ContainedBy` reports objects spanning multiple cells in the cell of their lower left corner instead of
+    /// using a hash set, and skips the test for objects within a cell that is entirely inside the query.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::aabbgrid::QueryMode;
+    /// use flat_spatial::shape::BoundingBox;
+    /// use flat_spatial::AABBGrid;
+    ///
+    /// let mut g: AABBGrid<(), BoundingBox<[f32; 2]>> = AABBGrid::new(10);
+    /// let room = g.insert(BoundingBox::new([0.0, 0.0], [50.0, 50.0]), ());
+    /// let table = g.insert(BoundingBox::new([10.0, 10.0], [20.0, 15.0]), ());
+    ///
+    /// let q = BoundingBox::new([5.0, 5.0], [25.0, 25.0]);
+    /// let ids = |mode| g.query_mode(q, mode).map(|(id, _, _)| id).collect::<Vec<_>>();
+    ///
+    /// assert_eq!(ids(QueryMode::ContainedBy), vec![table]);
+    /// assert_eq!(ids(QueryMode::Contains), vec![room]);
+    /// assert_eq!(ids(QueryMode::Intersects).len(), 2);
+    /// ```
+    pub fn query_mode(
+        &self,
+        aabb: AB,
+        mode: QueryMode,
+    ) -> impl Iterator<Item = (AABBGridHandle, &AB, &O)> + '_ {
+        let storage = &self.storage;
+
+        let ll_id = storage.cell_id(aabb.ll());
+        let ur_id = storage.cell_id(aabb.ur());
+        let last = if mode == QueryMode::Contains {
+            ll_id
+        } else {
+            ur_id
+        };
+        let mut dedup = fnv::FnvHashSet::with_hasher(fnv::FnvBuildHasher::default());
+
+        cell_range(ll_id, last)
+            .filter_map(move |id| Some((id, storage.cell(id)?)))
+            .flat_map(|(id, cell)| {
+                cell.objs
+                    .iter()
+                    .map(move |&(h, sing_cell)| (id, h, sing_cell))
+            })
+            .filter_map(move |(id, h, sing_cell)| {
+                // Safety: All objects in the cells are guaranteed to be valid.
+                let obj = unsafe { self.objects.get_unchecked(h) };
+                if mode == QueryMode::Intersects
+                    && !(sing_cell || ll_id == ur_id || dedup.insert(h))
+                {
+                    return None;
+                }
+                if !self.mode_hit(&aabb, mode, (ll_id, ur_id), id, &obj.aabb, sing_cell) {
+                    return None;
+                }
                 Some((h, &obj.aabb, &obj.obj))
-            } else {
-                None
+            })
+    }
+
+    /// Same as `query_mode` but uses a visitor for slightly better performance.
+    pub fn query_visitor_mode(
+        &self,
+        aabb: AB,
+        mode: QueryMode,
+        mut visitor: impl FnMut(AABBGridHandle, &AB, &O),
+    ) {
+        let storage = &self.storage;
+
+        let ll_id = storage.cell_id(aabb.ll());
+        let ur_id = storage.cell_id(aabb.ur());
+        let last = if mode == QueryMode::Contains {
+            ll_id
+        } else {
+            ur_id
+        };
+        let mut dedup = fnv::FnvHashSet::with_hasher(fnv::FnvBuildHasher::default());
+
+        for id in cell_range(ll_id, last) {
+            let cell = match storage.cell(id) {
+                Some(x) => x,
+                None => continue,
+            };
+
+            for &(h, sing_cell) in cell.objs.iter() {
+                // Safety: All objects in the cells are guaranteed to be valid.
+                let obj = unsafe { self.objects.get_unchecked(h) };
+                if mode == QueryMode::Intersects
+                    && !(sing_cell || ll_id == ur_id || dedup.insert(h))
+                {
+                    continue;
+                }
+                if self.mode_hit(&aabb, mode, (ll_id, ur_id), id, &obj.aabb, sing_cell) {
+                    visitor(h, &obj.aabb, &obj.obj);
+                }
+            }
+        }
+    }
+
+    /// Checks an object of the cell `id` against a `query_mode` query covering the cells from `ll_id` to `ur_id`.
+    /// Objects spanning multiple cells are already deduplicated for `Intersects`.
+    #[inline]
+    fn mode_hit(
+        &self,
+        aabb: &AB,
+        mode: QueryMode,
+        (ll_id, ur_id): (CellIdx, CellIdx),
+        id: CellIdx,
+        obj: &AB,
+        sing_cell: bool,
+    ) -> bool {
+        match mode {
+            QueryMode::Intersects => aabb.intersects(obj),
+            QueryMode::ContainedBy => {
+                if !sing_cell {
+                    return self.storage.cell_id(obj.ll()) == id && contains(aabb, obj);
+                }
+                let inner_cell =
+                    ll_id.0 < id.0 && id.0 < ur_id.0 && ll_id.1 < id.1 && id.1 < ur_id.1;
+                inner_cell || contains(aabb, obj)
             }
-        })
+            QueryMode::Contains => contains(obj, aabb),
+        }
+    }
+
+    /// Checks if any object intersects the given AABB, stopping at the first one found.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::shape::BoundingBox;
+    /// use flat_spatial::AABBGrid;
+    ///
+    /// let mut g: AABBGrid<(), BoundingBox<[f32; 2]>> = AABBGrid::new(10);
+    /// g.insert(BoundingBox::new([0.0, 0.0], [5.0, 5.0]), ());
+    ///
+    /// assert!(g.any_intersecting(BoundingBox::new([4.0, 4.0], [30.0, 30.0])));
+    /// assert!(!g.any_intersecting(BoundingBox::new([6.0, 6.0], [30.0, 30.0])));
+    /// ```
+    pub fn any_intersecting(&self, aabb: AB) -> bool {
+        let storage = &self.storage;
+
+        let ll_id = storage.cell_id(aabb.ll());
+        let ur_id = storage.cell_id(aabb.ur());
+
+        cell_range(ll_id, ur_id)
+            .flat_map(|id| storage.cell(id))
+            .flat_map(|cell| cell.objs.iter())
+            .any(|&(h, _)| {
+                // Safety: All objects in the cells are guaranteed to be valid.
+                let obj = unsafe { self.objects.get_unchecked(h) };
+                aabb.intersects(&obj.aabb)
+            })
     }
 
     /// Queries for all objects in the cells intersecting the given AABB
@@ -208,14 +731,9 @@ impl<O: Copy, AB: AABB> AABBGrid<O, AB> {
 
     /// Queries for objects intersecting a given AABB.
     /// Uses a visitor for slightly better performance.
-    pub fn query_visitor(&self, aabb: AB, mut visitor: impl FnMut(AABBGridHandle, &AB, &O)) {
-        self.query_broad_visitor(aabb, move |h| {
-            // Safety: All objects in the cells are guaranteed to be valid.
-            let obj = unsafe { self.objects.get_unchecked(h) };
-            if aabb.intersects(&obj.aabb) {
-                visitor(h, &obj.aabb, &obj.obj)
-            }
-        })
+    /// See `query_visitor_mode` to query the objects contained by or containing the AABB.
+    pub fn query_visitor(&self, aabb: AB, visitor: impl FnMut(AABBGridHandle, &AB, &O)) {
+        self.query_visitor_mode(aabb, QueryMode::Intersects, visitor)
     }
 
     /// Queries for all objects in the cells intersecting the given AABB
@@ -256,17 +774,477 @@ impl<O: Copy, AB: AABB> AABBGrid<O, AB> {
         }
     }
 
+    /// Queries for the objects whose AABB contains the point (or has it on its border), sorted by area
+    /// so that the smallest one comes first, for example to pick the most specific object under the mouse.
+    /// Only the cell containing the point is visited.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::AABBGrid;
+    /// use flat_spatial::shape::BoundingBox;
+    ///
+    /// let mut g: AABBGrid<(), BoundingBox<[f32; 2]>> = AABBGrid::new(10);
+    /// let window = g.insert(BoundingBox::new([0.0, 0.0], [100.0, 100.0]), ());
+    /// let button = g.insert(BoundingBox::new([10.0, 10.0], [20.0, 15.0]), ());
+    /// let _other = g.insert(BoundingBox::new([30.0, 10.0], [40.0, 15.0]), ());
+    ///
+    /// let picked: Vec<_> = g.query_point([12.0, 12.0]).map(|(id, _, _)| id).collect();
+    ///
+    /// assert_eq!(picked, vec![button, window]);
+    /// ```
+    pub fn query_point(&self, p: AB::V2) -> impl Iterator<Item = (AABBGridHandle, &AB, &O)> + '_ {
+        let mut hits: Vec<_> = self
+            .storage
+            .cell(self.storage.cell_id(p))
+            .into_iter()
+            .flat_map(|cell| cell.objs.iter())
+            .filter_map(|&(h, _)| {
+                // Safety: All objects in the cells are guaranteed to be valid.
+                let obj = unsafe { self.objects.get_unchecked(h) };
+                let (ll, ur) = (obj.aabb.ll(), obj.aabb.ur());
+                if ll.x() <= p.x() && p.x() <= ur.x() && ll.y() <= p.y() && p.y() <= ur.y() {
+                    Some((h, &obj.aabb, &obj.obj))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        let area = |aabb: &AB| {
+            let (ll, ur) = (aabb.ll(), aabb.ur());
+            (ur.x() - ll.x()) * (ur.y() - ll.y())
+        };
+        hits.sort_by(|a, b| {
+            area(a.1)
+                .partial_cmp(&area(b.1))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        hits.into_iter()
+    }
+
+    /// Queries for the objects whose AABB intersects a convex polygon, given in any winding order.
+    /// Only the cells intersecting the polygon are visited, so a rotated view doesn't look at all the cells of its bounding box.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::AABBGrid;
+    /// use flat_spatial::shape::BoundingBox;
+    ///
+    /// let mut g: AABBGrid<(), BoundingBox<[f32; 2]>> = AABBGrid::new(10);
+    /// let a = g.insert(BoundingBox::new([4.0, 4.0], [6.0, 34.0]), ());
+    /// let b = g.insert(BoundingBox::new([0.0, 0.0], [1.0, 1.0]), ());
+    ///
+    /// // A trapezoid, wider at the top, like the view of a top-down camera
+    /// let view = [[4.0, 0.0], [6.0, 0.0], [10.0, 10.0], [0.0, 10.0]];
+    /// let inside: Vec<_> = g.query_polygon(&view).map(|(id, _, _)| id).collect();
+    ///
+    /// assert_eq!(inside, vec![a]);
+    /// ```
+    pub fn query_polygon(
+        &self,
+        polygon: &[AB::V2],
+    ) -> impl Iterator<Item = (AABBGridHandle, &AB, &O)> + '_ {
+        let storage = &self.storage;
+        let polygon = PolygonQuery::new(polygon);
+        let rows = polygon_rows(storage.cell_size(), &polygon.points);
+        let mut seen = fnv::FnvHashSet::with_hasher(fnv::FnvBuildHasher::default());
+
+        rows.into_iter()
+            .flat_map(move |(y, min_x, max_x)| {
+                (min_x..=max_x).flat_map(move |x| storage.cell((x, y)))
+            })
+            .flat_map(|x| x.objs.iter().copied())
+            .filter(move |&(h, sing_cell)| sing_cell || seen.insert(h))
+            .filter_map(move |(h, _)| {
+                // Safety: All objects in the cells are guaranteed to be valid.
+                let obj = unsafe { self.objects.get_unchecked(h) };
+                let (ll, ur) = (obj.aabb.ll(), obj.aabb.ur());
+                if polygon.intersects_box([ll.x(), ll.y()], [ur.x(), ur.y()]) {
+                    Some((h, &obj.aabb, &obj.obj))
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// Sweeps the AABB along `velocity` and returns the objects it hits, sorted by time of impact, to avoid
+    /// fast objects tunneling through thin ones. Only the cells covered by the swept volume are visited.
+    /// The bounding boxes of the objects are used, even if `AABB::intersects` is exact.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::shape::BoundingBox;
+    /// use flat_spatial::AABBGrid;
+    ///
+    /// let mut g: AABBGrid<(), BoundingBox<[f32; 2]>> = AABBGrid::new(10);
+    /// let wall = g.insert(BoundingBox::new([50.0, -20.0], [51.0, 20.0]), ());
+    ///
+    /// let bullet = BoundingBox::new([0.0, 0.0], [1.0, 1.0]);
+    /// let hits = g.sweep(bullet, [100.0, 0.0]);
+    ///
+    /// assert_eq!(hits.len(), 1);
+    /// assert_eq!(hits[0].handle, wall);
+    /// assert_eq!(hits[0].toi, 0.49);
+    /// assert_eq!(hits[0].normal, [-1.0, 0.0]);
+    /// ```
+    pub fn sweep(&self, aabb: AB, velocity: AB::V2) -> Vec<SweepHit<AB::V2>> {
+        let storage = &self.storage;
+        let ll = [aabb.ll().x(), aabb.ll().y()];
+        let ur = [aabb.ur().x(), aabb.ur().y()];
+        let v = [velocity.x(), velocity.y()];
+
+        let mut dedup = fnv::FnvHashSet::with_hasher(fnv::FnvBuildHasher::default());
+        let mut hits = vec![];
+
+        for (y, min_x, max_x) in polygon_rows(storage.cell_size(), &swept_hull(ll, ur, v)) {
+            for x in min_x..=max_x {
+                let cell = match storage.cell((x, y)) {
+                    Some(x) => x,
+                    None => continue,
+                };
+
+                for &(h, sing_cell) in cell.objs.iter() {
+                    if !sing_cell && !dedup.insert(h) {
+                        continue;
+                    }
+                    // Safety: All objects in the cells are guaranteed to be valid.
+                    let obj = unsafe { self.objects.get_unchecked(h) };
+                    let (oll, our) = (obj.aabb.ll(), obj.aabb.ur());
+                    if let Some((toi, normal)) =
+                        sweep_box(ll, ur, v, [oll.x(), oll.y()], [our.x(), our.y()])
+                    {
+                        hits.push(SweepHit {
+                            handle: h,
+                            toi,
+                            normal: normal.into(),
+                        });
+                    }
+                }
+            }
+        }
+
+        hits.sort_by(|a, b| {
+            a.toi
+                .partial_cmp(&b.toi)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        hits
+    }
+
+    /// Queries for objects intersecting the given shape, which can be of another type than the stored objects.
+    /// The bounding box of the shape is used to find the cells, then the exact `Shape::intersects_shape` test
+    /// is run on each object.
+    /// Objects spanning multiple cells are reported only once, without needing a hash set.
+    pub fn query_shape<'a, S: Shape + 'a>(
+        &'a self,
+        shape: S,
+    ) -> impl Iterator<Item = (AABBGridHandle, &'a AB, &'a O)> + 'a
+    where
+        AB: Shape,
+    {
+        let storage = &self.storage;
+        let bbox = shape.bounding_aabb();
+        let q_ll = storage.cell_id(bbox.ll);
+        let q_ur = storage.cell_id(bbox.ur);
+
+        cell_range(q_ll, q_ur)
+            .filter_map(move |id| Some((id, storage.cell(id)?)))
+            .flat_map(move |(id, cell)| {
+                cell.objs.iter().filter_map(move |&(h, sing_cell)| {
+                    // Safety: All objects in the cells are guaranteed to be valid.
+                    let obj = unsafe { self.objects.get_unchecked(h) };
+                    if !sing_cell {
+                        // Only report the object in the first cell shared by the query and the object
+                        let obj_ll = storage.cell_id(obj.aabb.ll());
+                        if !first_shared_cell(q_ll, obj_ll, id) {
+                            return None;
+                        }
+                    }
+                    if !shape.intersects_shape(&obj.aabb) {
+                        return None;
+                    }
+                    Some((h, &obj.aabb, &obj.obj))
+                })
+            })
+    }
+
+    /// Runs many queries at once, writing the handles of the objects intersecting each aabb into `out`.
+    /// The queries are grouped by the cells they cover: each cell is looked up once and its objects are tested
+    /// against all the queries covering it, instead of every query hashing its own cells.
+    /// The hits of a query are not in the same order as with `query`.
+    /// This is faster than separate `query` calls when the queries are in random order, but sorting the covered
+    /// cells can cost more than the lookups it saves when the queries are already sorted spatially.
+    /// Objects spanning multiple cells are reported only once per query, without needing a hash set.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::AABBGrid;
+    /// use flat_spatial::batch::BatchResults;
+    /// use euclid::default::Rect;
+    ///
+    /// let mut g: AABBGrid<(), Rect<f32>> = AABBGrid::new(10);
+    /// let a = g.insert(Rect::new([0.0, 0.0].into(), [25.0, 5.0].into()), ());
+    ///
+    /// let mut out = BatchResults::new();
+    /// g.query_batch(&[Rect::new([1.0, 1.0].into(), [20.0, 1.0].into()), Rect::new([30.0, 1.0].into(), [1.0, 1.0].into())], &mut out);
+    ///
+    /// assert_eq!(out.get(0), &[a]);
+    /// assert!(out.get(1).is_empty());
+    /// ```
+    pub fn query_batch(&self, queries: &[AB], out: &mut BatchResults<AABBGridHandle>) {
+        let storage = &self.storage;
+
+        out.fill(
+            queries
+                .iter()
+                .map(|q| (storage.cell_id(q.ll()), storage.cell_id(q.ur()))),
+            |id| storage.cell(id),
+            |cell, id, q, hits| {
+                let aabb = &queries[q];
+                let mut q_ll = None;
+                for &(h, sing_cell) in cell.objs.iter() {
+                    // Safety: All objects in the cells are guaranteed to be valid.
+                    let obj = unsafe { self.objects.get_unchecked(h) };
+                    if !sing_cell {
+                        // Only report the object in the first cell shared by the query and the object
+                        let q_ll = *q_ll.get_or_insert_with(|| storage.cell_id(aabb.ll()));
+                        let obj_ll = storage.cell_id(obj.aabb.ll());
+                        if !first_shared_cell(q_ll, obj_ll, id) {
+                            continue;
+                        }
+                    }
+                    if aabb.intersects(&obj.aabb) {
+                        hits.push(h);
+                    }
+                }
+            },
+        );
+    }
+
+    /// Checks the internal invariants of the grid: every cell entry points to a live object,
+    /// every object is stored exactly once in each of the cells its aabb covers with the right single cell flag,
+    /// and the cells of objects with pending lazy updates are dirty.
+    /// Useful after deserializing untrusted data, as a corrupted grid can lead to undefined behavior in queries.
+    /// At most `MAX_ERRORS` errors are reported, and an object missing from many cells is reported once.
+    pub fn validate(&self) -> Result<(), Vec<ValidationError<AABBGridHandle>>> {
+        let storage = &self.storage;
+        let mut errors = vec![];
+        let mut seen = fnv::FnvHashSet::default();
+        let mut n_entries = fnv::FnvHashMap::<AABBGridHandle, usize>::default();
+
+        'cells: for (&id, cell) in storage.cells.iter() {
+            for &(handle, sing_cell) in cell.objs.iter() {
+                if errors.len() >= MAX_ERRORS {
+                    break 'cells;
+                }
+                let obj = match self.objects.get(handle) {
+                    Some(x) => x,
+                    None => {
+                        errors.push(ValidationError::DeadHandle { cell: id, handle });
+                        continue;
+                    }
+                };
+
+                if !seen.insert((id, handle)) {
+                    errors.push(ValidationError::Duplicate { cell: id, handle });
+                    continue;
+                }
+
+                let ll = storage.cell_id(obj.aabb.ll());
+                let ur = storage.cell_id(obj.aabb.ur());
+                if !(ll.0..=ur.0).contains(&id.0) || !(ll.1..=ur.1).contains(&id.1) {
+                    errors.push(ValidationError::WrongCell { cell: id, handle });
+                    continue;
+                }
+                *n_entries.entry(handle).or_default() += 1;
+
+                if sing_cell != (ll == ur) {
+                    errors.push(ValidationError::WrongSingleCellFlag { cell: id, handle });
+                }
+
+                if !matches!(obj.state, ObjectState::Unchanged) && !cell.dirty {
+                    errors.push(ValidationError::NotDirty { cell: id, handle });
+                }
+            }
+        }
+
+        for (handle, obj) in self.objects.iter() {
+            if errors.len() >= MAX_ERRORS {
+                break;
+            }
+            let ll = storage.cell_id(obj.aabb.ll());
+            let ur = storage.cell_id(obj.aabb.ur());
+            // The entries counted are in distinct covered cells, so comparing the counts is enough,
+            // and one of the first n + 1 covered cells is missing otherwise.
+            let n = n_entries.get(&handle).copied().unwrap_or(0);
+            if (n as u128) < cell_count(ll, ur) {
+                let missing = cell_range(ll, ur)
+                    .take(n + 1)
+                    .find(|&id| !seen.contains(&(id, handle)));
+                if let Some(cell) = missing {
+                    errors.push(ValidationError::MissingFromCell { cell, handle });
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
     /// Returns the number of objects currently available
+    /// (lazy removals that were not confirmed with maintain() are still counted)
     pub fn len(&self) -> usize {
         self.objects.len()
     }
 
     /// Checks if the grid contains objects or not
+    /// (lazy removals that were not confirmed with maintain() are still counted)
     pub fn is_empty(&self) -> bool {
         self.objects.is_empty()
     }
 }
 
+#[cfg(feature = "rayon")]
+impl<O: Copy + Send + Sync, AB: AABB + Send + Sync> AABBGrid<O, AB> {
+    /// Same as maintain_deterministic() but the dirty cells are processed in parallel using rayon.
+    pub fn par_maintain(&mut self) {
+        use rayon::prelude::*;
+
+        let Self {
+            storage,
+            objects,
+            to_relocate,
+            ..
+        } = self;
+
+        let cell_size = storage.cell_size();
+        let objects_ref = &*objects;
+        let mut relocated = storage
+            .cells
+            .par_iter_mut()
+            .filter(|(_, cell)| cell.dirty)
+            .fold(Vec::new, |mut relocated, (id, cell)| {
+                cell.maintain(*id, cell_size, objects_ref, &mut relocated);
+                relocated
+            })
+            .reduce(Vec::new, |mut relocated, mut r| {
+                relocated.append(&mut r);
+                relocated
+            });
+
+        storage.modify(|cell| cell.objs.is_empty());
+
+        to_relocate.append(&mut relocated);
+        to_relocate.sort_unstable();
+        self.apply_relocations();
+    }
+
+    /// Runs many independent queries concurrently using rayon.
+    /// Returns the result of `f` for each query, in the same order as `queries`.
+    pub fn par_query_batch<Q: Sync, R: Send>(
+        &self,
+        queries: &[Q],
+        f: impl Fn(&Self, &Q) -> R + Sync + Send,
+    ) -> Vec<R> {
+        use rayon::prelude::*;
+
+        queries.par_iter().map(|q| f(self, q)).collect()
+    }
+}
+
+/// Convex hull of a box and its translation by `v`, counter-clockwise
+fn swept_hull(ll: [f32; 2], ur: [f32; 2], v: [f32; 2]) -> Vec<[f32; 2]> {
+    let mut points = Vec::with_capacity(8);
+    for c in [ll, [ur[0], ll[1]], ur, [ll[0], ur[1]]] {
+        points.push(c);
+        points.push([c[0] + v[0], c[1] + v[1]]);
+    }
+    points.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+    // Monotone chain
+    let cross = |o: [f32; 2], a: [f32; 2], b: [f32; 2]| {
+        (a[0] - o[0]) * (b[1] - o[1]) - (a[1] - o[1]) * (b[0] - o[0])
+    };
+    let mut hull: Vec<[f32; 2]> = Vec::with_capacity(8);
+    for pass in 0..2 {
+        let start = hull.len();
+        for i in 0..points.len() {
+            let p = if pass == 0 {
+                points[i]
+            } else {
+                points[points.len() - 1 - i]
+            };
+            while hull.len() >= start + 2
+                && cross(hull[hull.len() - 2], hull[hull.len() - 1], p) <= 0.0
+            {
+                hull.pop();
+            }
+            hull.push(p);
+        }
+        // The last point is the first one of the other chain
+        hull.pop();
+    }
+    if hull.is_empty() {
+        hull.push(points[0]);
+    }
+    hull
+}
+
+/// Time of impact and normal of the moving box (ll, ur) with velocity `v` against the box (oll, our),
+/// if they touch within one step
+fn sweep_box(
+    ll: [f32; 2],
+    ur: [f32; 2],
+    v: [f32; 2],
+    oll: [f32; 2],
+    our: [f32; 2],
+) -> Option<(f32, [f32; 2])> {
+    let mut enter = f32::NEG_INFINITY;
+    let mut exit = f32::INFINITY;
+    let mut normal = [0.0; 2];
+
+    for i in 0..2 {
+        if v[i] == 0.0 {
+            if ur[i] < oll[i] || our[i] < ll[i] {
+                return None;
+            }
+            continue;
+        }
+
+        let (near, far) = if v[i] > 0.0 {
+            ((oll[i] - ur[i]) / v[i], (our[i] - ll[i]) / v[i])
+        } else {
+            ((our[i] - ll[i]) / v[i], (oll[i] - ur[i]) / v[i])
+        };
+        if near > enter {
+            enter = near;
+            normal = [0.0; 2];
+            normal[i] = -v[i].signum();
+        }
+        exit = exit.min(far);
+    }
+
+    if enter > exit || enter > 1.0 || exit < 0.0 {
+        return None;
+    }
+    if enter <= 0.0 {
+        // Already touching at the start
+        return Some((0.0, [0.0; 2]));
+    }
+    Some((enter, normal))
+}
+
+/// Checks if `inner` is inside `outer`, borders included
+fn contains<AB: AABB>(outer: &AB, inner: &AB) -> bool {
+    let (oll, our) = (outer.ll(), outer.ur());
+    let (ill, iur) = (inner.ll(), inner.ur());
+    oll.x() <= ill.x() && oll.y() <= ill.y() && iur.x() <= our.x() && iur.y() <= our.y()
+}
+
 fn cells_apply<AB: AABB>(
     storage: &mut SparseStorage<AABBGridCell>,
     bbox: &AB,
@@ -279,6 +1257,42 @@ fn cells_apply<AB: AABB>(
     }
 }
 
+/// Pushes an entry for each cell covered by the aabb, to be given to `fill_cells`.
+fn push_cells<AB: AABB>(
+    storage: &SparseStorage<AABBGridCell>,
+    bbox: &AB,
+    h: AABBGridHandle,
+    by_cell: &mut Vec<(CellIdx, (AABBGridHandle, bool))>,
+) {
+    let ll = storage.cell_id(bbox.ll());
+    let ur = storage.cell_id(bbox.ur());
+    for id in cell_range(ll, ur) {
+        by_cell.push((id, (h, ll == ur)));
+    }
+}
+
+/// Inserts the entries in their cells, allocating each cell only once.
+fn fill_cells(
+    storage: &mut SparseStorage<AABBGridCell>,
+    mut by_cell: Vec<(CellIdx, (AABBGridHandle, bool))>,
+) {
+    by_cell.sort_unstable_by_key(|&(id, (h, _))| (id, h));
+
+    for group in GroupByCell(&by_cell) {
+        let cell = storage.cell_mut_unchecked(group[0].0);
+        cell.objs.reserve_exact(group.len());
+        cell.objs.extend(group.iter().map(|&(_, obj)| obj));
+    }
+}
+
+fn mark_dirty<AB: AABB>(storage: &mut SparseStorage<AABBGridCell>, bbox: &AB) {
+    let ll = storage.cell_id(bbox.ll());
+    let ur = storage.cell_id(bbox.ur());
+    for id in cell_range(ll, ur) {
+        storage.cell_mut_unchecked(id).dirty = true;
+    }
+}
+
 enum QueryIter<T: Iterator<Item = (AABBGridHandle, bool)>> {
     Simple(T),
     Dedup(fnv::FnvHashSet<AABBGridHandle>, T),
@@ -0,0 +1,702 @@
+//! Zero-copy archives of grids, available with the `rkyv` feature.
+//!
+//! `FrozenGridData` and `AABBGridData` are flat copies of a `FrozenGrid` or an `AABBGrid` that can be archived
+//! with rkyv. The archived versions (`ArchivedFrozenGridData` and `ArchivedAABBGridData`) can be queried directly
+//! on the archived bytes, for example from a memory-mapped file, without deserializing anything.
+//! Queries give the same results as on the live structure, with the same handles: `AABBGridData` keeps the exact
+//! shape of every object (see `ArchiveShape`), so circles or polygons are not approximated by their aabbs.
+//!
+//! ```rust
+//! use flat_spatial::Grid;
+//! use flat_spatial::archive::FrozenGridData;
+//!
+//! let mut g: Grid<u32, [f32; 2]> = Grid::new(10);
+//! let a = g.insert([3.0, 3.0], 1);
+//! let _b = g.insert([12.0, -8.0], 2);
+//!
+//! let bytes = rkyv::to_bytes::<_, 256>(&FrozenGridData::from(&g.freeze())).unwrap();
+//! let archived = rkyv::check_archived_root::<FrozenGridData<u32>>(&bytes).unwrap();
+//! archived.validate().unwrap();
+//!
+//! let around: Vec<_> = archived.query_around([2.0, 2.0], 5.0).map(|(id, _pos)| id).collect();
+//! assert_eq!(around, vec![a]);
+//! assert_eq!(archived.get::<[f32; 2]>(a), Some(([3.0, 3.0], &1)));
+//! ```
+//!
+//! `rkyv::check_archived_root` only checks that the bytes are a well-formed archive, not that the cells
+//! match the objects. Bytes that come from an untrusted source must also be checked with
+//! `validate`, otherwise queries can panic or give wrong results.
+
+use crate::aabbgrid::AABBGridHandle;
+use crate::frozen::{morton, unmorton, valid_layout};
+use crate::grid::GridHandle;
+use crate::shape::{
+    AnyShape, BoundingBox, Capsule, Circle, ConvexPolygon, Obb, Shape, MAX_POLYGON_VERTICES,
+};
+use crate::storage::{cell_count, cell_id, cell_range, first_shared_cell, CellIdx};
+use crate::validate::{CorruptedGrid, ValidationError, MAX_ERRORS};
+use crate::{AABBGrid, FrozenGrid, Vec2, AABB};
+use rkyv::{Archive, Deserialize, Serialize};
+use slotmapd::{Key, KeyData};
+
+fn to_ffi(k: impl Key) -> u64 {
+    k.data().as_ffi()
+}
+
+fn from_ffi<K: Key>(x: u64) -> K {
+    K::from(KeyData::from_ffi(x))
+}
+
+/// Index of the given cell in the sorted morton keys
+fn find_cell(keys: &[u64], id: CellIdx) -> Option<usize> {
+    keys.binary_search(&morton(id)).ok()
+}
+
+/// A point stored in a cell
+#[derive(Archive, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[archive(check_bytes)]
+pub struct PointEntry {
+    handle: u64,
+    pos: [f32; 2],
+}
+
+/// A point and its associated object
+#[derive(Archive, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[archive(check_bytes)]
+pub struct PointObject<O> {
+    handle: u64,
+    pos: [f32; 2],
+    obj: O,
+}
+
+/// A flat copy of a `FrozenGrid` that can be archived with rkyv.
+/// Query it through `ArchivedFrozenGridData`.
+#[derive(Archive, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[archive(check_bytes)]
+pub struct FrozenGridData<O> {
+    cell_size: i32,
+    /// Morton keys of the non-empty cells, sorted
+    keys: Vec<u64>,
+    /// offsets[i]..offsets[i + 1] is the range of the i-th cell in `entries`
+    offsets: Vec<u32>,
+    entries: Vec<PointEntry>,
+    /// Sorted by handle
+    objects: Vec<PointObject<O>>,
+}
+
+impl<O: Copy, V2: Vec2> From<&FrozenGrid<O, V2>> for FrozenGridData<O> {
+    fn from(g: &FrozenGrid<O, V2>) -> Self {
+        let mut objects: Vec<_> = g
+            .objects
+            .iter()
+            .map(|(h, x)| PointObject {
+                handle: to_ffi(h),
+                pos: [x.pos.x(), x.pos.y()],
+                obj: x.obj,
+            })
+            .collect();
+        objects.sort_unstable_by_key(|x| x.handle);
+
+        Self {
+            cell_size: g.cell_size(),
+            keys: g.keys.clone(),
+            offsets: g.offsets.clone(),
+            entries: g
+                .entries
+                .iter()
+                .map(|&(h, pos)| PointEntry {
+                    handle: to_ffi(h),
+                    pos: [pos.x(), pos.y()],
+                })
+                .collect(),
+            objects,
+        }
+    }
+}
+
+impl<O: Archive> ArchivedFrozenGridData<O> {
+    pub fn cell_size(&self) -> i32 {
+        self.cell_size
+    }
+
+    /// Returns the number of objects
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// Checks if the grid contains objects or not
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// Returns a reference to the associated archived object and its position, using the handle.
+    pub fn get<V2: Vec2>(&self, id: GridHandle) -> Option<(V2, &O::Archived)> {
+        let handle = to_ffi(id);
+        let i = self
+            .objects
+            .binary_search_by_key(&handle, |x| x.handle)
+            .ok()?;
+        let obj = &self.objects[i];
+        Some((obj.pos.into(), &obj.obj))
+    }
+
+    /// Checks that the archive is consistent: the cell keys and the objects are sorted, the cell offsets
+    /// match the entries, and every object is stored once, in its cell, with its position.
+    ///
+    /// This must be called on bytes that come from an untrusted source before querying them,
+    /// as `rkyv::check_archived_root` does not check these invariants.
+    pub fn validate(&self) -> Result<(), CorruptedGrid<GridHandle>> {
+        if self.cell_size <= 0 {
+            return Err(CorruptedGrid::InvalidCellSize(self.cell_size));
+        }
+        if !valid_layout(&self.keys, &self.offsets, self.entries.len())
+            || !self.objects.windows(2).all(|w| w[0].handle < w[1].handle)
+        {
+            return Err(CorruptedGrid::InvalidLayout);
+        }
+
+        let mut errors = vec![];
+        let mut seen = vec![false; self.objects.len()];
+
+        for (i, &key) in self.keys.iter().enumerate() {
+            let cell = unmorton(key);
+            for entry in &self.entries[self.offsets[i] as usize..self.offsets[i + 1] as usize] {
+                let handle = from_ffi(entry.handle);
+                let j = match self
+                    .objects
+                    .binary_search_by_key(&entry.handle, |x| x.handle)
+                {
+                    Ok(j) => j,
+                    Err(_) => {
+                        errors.push(ValidationError::DeadHandle { cell, handle });
+                        continue;
+                    }
+                };
+
+                if seen[j] {
+                    errors.push(ValidationError::Duplicate { cell, handle });
+                    continue;
+                }
+                seen[j] = true;
+
+                let obj = &self.objects[j];
+                if cell_id(self.cell_size, obj.pos) != cell {
+                    errors.push(ValidationError::WrongCell { cell, handle });
+                    continue;
+                }
+
+                if entry.pos[0].to_bits() != obj.pos[0].to_bits()
+                    || entry.pos[1].to_bits() != obj.pos[1].to_bits()
+                {
+                    errors.push(ValidationError::PositionMismatch { cell, handle });
+                }
+            }
+        }
+
+        for (obj, seen) in self.objects.iter().zip(seen) {
+            if !seen {
+                errors.push(ValidationError::MissingFromCell {
+                    cell: cell_id(self.cell_size, obj.pos),
+                    handle: from_ffi(obj.handle),
+                });
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(CorruptedGrid::Invalid(errors))
+        }
+    }
+
+    fn cell(&self, id: CellIdx) -> &[ArchivedPointEntry] {
+        match find_cell(&self.keys, id) {
+            Some(i) => &self.entries[self.offsets[i] as usize..self.offsets[i + 1] as usize],
+            None => &[],
+        }
+    }
+
+    /// Same as `FrozenGrid::query_around`
+    pub fn query_around<'a, V2: Vec2 + 'a>(
+        &'a self,
+        pos: V2,
+        radius: f32,
+    ) -> impl Iterator<Item = (GridHandle, V2)> + 'a {
+        let ll = [pos.x() - radius, pos.y() - radius];
+        let ur = [pos.x() + radius, pos.y() + radius];
+
+        let radius2 = radius * radius;
+        self.query(V2::from(ll), V2::from(ur))
+            .filter(move |(_, pos_obj)| {
+                let x = pos_obj.x() - pos.x();
+                let y = pos_obj.y() - pos.y();
+                x * x + y * y < radius2
+            })
+    }
+
+    /// Same as `FrozenGrid::query_aabb`
+    pub fn query_aabb<'a, V2: Vec2 + 'a>(
+        &'a self,
+        ll_: V2,
+        ur_: V2,
+    ) -> impl Iterator<Item = (GridHandle, V2)> + 'a {
+        let ll = [ll_.x().min(ur_.x()), ll_.y().min(ur_.y())];
+        let ur = [ll_.x().max(ur_.x()), ll_.y().max(ur_.y())];
+
+        self.query(V2::from(ll), V2::from(ur))
+            .filter(move |(_, pos_obj)| {
+                (ll[0]..=ur[0]).contains(&pos_obj.x()) && (ll[1]..=ur[1]).contains(&pos_obj.y())
+            })
+    }
+
+    /// Same as `FrozenGrid::query`
+    pub fn query<'a, V2: Vec2 + 'a>(
+        &'a self,
+        ll: V2,
+        ur: V2,
+    ) -> impl Iterator<Item = (GridHandle, V2)> + 'a {
+        let ll_id = cell_id(self.cell_size, ll);
+        let ur_id = cell_id(self.cell_size, ur);
+
+        cell_range(ll_id, ur_id)
+            .flat_map(move |id| self.cell(id).iter())
+            .map(|x| (from_ffi(x.handle), V2::from(x.pos)))
+    }
+}
+
+/// An object stored in a cell, as an index into the objects
+#[derive(Archive, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[archive(check_bytes)]
+pub struct AABBEntry {
+    object: u32,
+    sing_cell: bool,
+}
+
+/// An aabb and its associated object
+#[derive(Archive, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[archive(check_bytes)]
+pub struct AABBObject<O> {
+    handle: u64,
+    ll: [f32; 2],
+    ur: [f32; 2],
+    shape: ShapeData,
+    obj: O,
+}
+
+/// The exact shape of an archived object, so that archived queries use the same narrow phase as `AABBGrid`.
+/// Boxes only need their corners, which are stored with every object.
+#[derive(Archive, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[archive(check_bytes)]
+pub enum ShapeData {
+    Box,
+    Circle {
+        center: [f32; 2],
+        radius: f32,
+    },
+    Capsule {
+        a: [f32; 2],
+        b: [f32; 2],
+        radius: f32,
+    },
+    Obb {
+        center: [f32; 2],
+        half_extents: [f32; 2],
+        axis: [f32; 2],
+    },
+    Polygon {
+        points: [[f32; 2]; MAX_POLYGON_VERTICES],
+        len: u8,
+    },
+}
+
+/// An aabb that can be stored in an `AABBGridData` along with its exact shape.
+///
+/// It is implemented for the shapes of the `shape` module and for the aabbs of euclid and parry2d.
+/// Other aabbs with the default `AABB::intersects` can be stored as `ShapeData::Box`.
+pub trait ArchiveShape: AABB {
+    fn to_data(&self) -> ShapeData;
+
+    /// Rebuilds the shape from its corners and its data, `None` if it is another kind of shape
+    fn from_data(ll: [f32; 2], ur: [f32; 2], data: &ShapeData) -> Option<Self>;
+}
+
+fn arr(v: impl Vec2) -> [f32; 2] {
+    [v.x(), v.y()]
+}
+
+impl<V2: Vec2> ArchiveShape for BoundingBox<V2> {
+    fn to_data(&self) -> ShapeData {
+        ShapeData::Box
+    }
+
+    fn from_data(ll: [f32; 2], ur: [f32; 2], data: &ShapeData) -> Option<Self> {
+        match *data {
+            ShapeData::Box => Some(BoundingBox::new(ll.into(), ur.into())),
+            _ => None,
+        }
+    }
+}
+
+impl<V2: Vec2> ArchiveShape for Circle<V2> {
+    fn to_data(&self) -> ShapeData {
+        ShapeData::Circle {
+            center: arr(self.center),
+            radius: self.radius,
+        }
+    }
+
+    fn from_data(_: [f32; 2], _: [f32; 2], data: &ShapeData) -> Option<Self> {
+        match *data {
+            ShapeData::Circle { center, radius } => Some(Circle::new(center.into(), radius)),
+            _ => None,
+        }
+    }
+}
+
+impl<V2: Vec2> ArchiveShape for Capsule<V2> {
+    fn to_data(&self) -> ShapeData {
+        ShapeData::Capsule {
+            a: arr(self.a),
+            b: arr(self.b),
+            radius: self.radius,
+        }
+    }
+
+    fn from_data(_: [f32; 2], _: [f32; 2], data: &ShapeData) -> Option<Self> {
+        match *data {
+            ShapeData::Capsule { a, b, radius } => Some(Capsule::new(a.into(), b.into(), radius)),
+            _ => None,
+        }
+    }
+}
+
+impl<V2: Vec2> ArchiveShape for Obb<V2> {
+    fn to_data(&self) -> ShapeData {
+        ShapeData::Obb {
+            center: arr(self.center),
+            half_extents: arr(self.half_extents),
+            axis: arr(self.axis),
+        }
+    }
+
+    fn from_data(_: [f32; 2], _: [f32; 2], data: &ShapeData) -> Option<Self> {
+        match *data {
+            ShapeData::Obb {
+                center,
+                half_extents,
+                axis,
+            } => Some(Obb {
+                center: center.into(),
+                half_extents: half_extents.into(),
+                axis: axis.into(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl<V2: Vec2> ArchiveShape for ConvexPolygon<V2> {
+    fn to_data(&self) -> ShapeData {
+        let mut points = [arr(self.points()[0]); MAX_POLYGON_VERTICES];
+        for (p, &v) in points.iter_mut().zip(self.points()) {
+            *p = arr(v);
+        }
+        ShapeData::Polygon {
+            points,
+            len: self.points().len() as u8,
+        }
+    }
+
+    fn from_data(_: [f32; 2], _: [f32; 2], data: &ShapeData) -> Option<Self> {
+        match *data {
+            ShapeData::Polygon { points, len } => {
+                let points = points.get(..len as usize).filter(|x| !x.is_empty())?;
+                let points: Vec<V2> = points.iter().map(|&p| p.into()).collect();
+                Some(ConvexPolygon::new(&points))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<V2: Vec2> ArchiveShape for AnyShape<V2> {
+    fn to_data(&self) -> ShapeData {
+        match self {
+            AnyShape::Box(x) => x.to_data(),
+            AnyShape::Circle(x) => x.to_data(),
+            AnyShape::Capsule(x) => x.to_data(),
+            AnyShape::Obb(x) => x.to_data(),
+            AnyShape::Polygon(x) => x.to_data(),
+        }
+    }
+
+    fn from_data(ll: [f32; 2], ur: [f32; 2], data: &ShapeData) -> Option<Self> {
+        Some(match data {
+            ShapeData::Box => AnyShape::Box(ArchiveShape::from_data(ll, ur, data)?),
+            ShapeData::Circle { .. } => AnyShape::Circle(ArchiveShape::from_data(ll, ur, data)?),
+            ShapeData::Capsule { .. } => AnyShape::Capsule(ArchiveShape::from_data(ll, ur, data)?),
+            ShapeData::Obb { .. } => AnyShape::Obb(ArchiveShape::from_data(ll, ur, data)?),
+            ShapeData::Polygon { .. } => AnyShape::Polygon(ArchiveShape::from_data(ll, ur, data)?),
+        })
+    }
+}
+
+#[cfg(feature = "euclid")]
+impl<U> ArchiveShape for euclid::Rect<f32, U> {
+    fn to_data(&self) -> ShapeData {
+        ShapeData::Box
+    }
+
+    fn from_data(ll: [f32; 2], ur: [f32; 2], data: &ShapeData) -> Option<Self> {
+        match *data {
+            ShapeData::Box => Some(euclid::Rect::new(
+                ll.into(),
+                euclid::Size2D::new(ur[0] - ll[0], ur[1] - ll[1]),
+            )),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "parry2d")]
+impl ArchiveShape for parry2d::bounding_volume::Aabb {
+    fn to_data(&self) -> ShapeData {
+        ShapeData::Box
+    }
+
+    fn from_data(ll: [f32; 2], ur: [f32; 2], data: &ShapeData) -> Option<Self> {
+        match *data {
+            ShapeData::Box => Some(parry2d::bounding_volume::Aabb::new(ll.into(), ur.into())),
+            _ => None,
+        }
+    }
+}
+
+/// A flat copy of an `AABBGrid` that can be archived with rkyv.
+/// Query it through `ArchivedAABBGridData`.
+#[derive(Archive, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[archive(check_bytes)]
+pub struct AABBGridData<O> {
+    cell_size: i32,
+    /// Morton keys of the non-empty cells, sorted
+    keys: Vec<u64>,
+    /// offsets[i]..offsets[i + 1] is the range of the i-th cell in `entries`
+    offsets: Vec<u32>,
+    entries: Vec<AABBEntry>,
+    /// Sorted by handle
+    objects: Vec<AABBObject<O>>,
+}
+
+impl<O: Copy, AB: ArchiveShape> From<&AABBGrid<O, AB>> for AABBGridData<O> {
+    fn from(g: &AABBGrid<O, AB>) -> Self {
+        let mut objects: Vec<_> = g
+            .objects
+            .iter()
+            .map(|(h, x)| AABBObject {
+                handle: to_ffi(h),
+                ll: [x.aabb.ll().x(), x.aabb.ll().y()],
+                ur: [x.aabb.ur().x(), x.aabb.ur().y()],
+                shape: x.aabb.to_data(),
+                obj: x.obj,
+            })
+            .collect();
+        objects.sort_unstable_by_key(|x| x.handle);
+
+        let index = |h: AABBGridHandle| {
+            objects
+                .binary_search_by_key(&to_ffi(h), |x| x.handle)
+                .unwrap() as u32
+        };
+
+        let mut cells: Vec<_> = g
+            .storage()
+            .cells
+            .iter()
+            .filter(|(_, cell)| !cell.objs.is_empty())
+            .map(|(id, cell)| (morton(*id), cell))
+            .collect();
+        cells.sort_unstable_by_key(|(key, _)| *key);
+
+        let mut keys = Vec::with_capacity(cells.len());
+        let mut offsets = Vec::with_capacity(cells.len() + 1);
+        let mut entries = Vec::new();
+
+        offsets.push(0);
+        for (key, cell) in cells {
+            keys.push(key);
+            entries.extend(cell.objs.iter().map(|&(h, sing_cell)| AABBEntry {
+                object: index(h),
+                sing_cell,
+            }));
+            offsets.push(entries.len() as u32);
+        }
+
+        Self {
+            cell_size: g.storage().cell_size(),
+            keys,
+            offsets,
+            entries,
+            objects,
+        }
+    }
+}
+
+impl<O: Archive> ArchivedAABBGridData<O> {
+    pub fn cell_size(&self) -> i32 {
+        self.cell_size
+    }
+
+    /// Returns the number of objects
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// Checks if the grid contains objects or not
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// Returns the lower left and upper right corners of the aabb and a reference to the associated archived object,
+    /// using the handle.
+    pub fn get<V2: Vec2>(&self, id: AABBGridHandle) -> Option<(V2, V2, &O::Archived)> {
+        let handle = to_ffi(id);
+        let i = self
+            .objects
+            .binary_search_by_key(&handle, |x| x.handle)
+            .ok()?;
+        let obj = &self.objects[i];
+        Some((obj.ll.into(), obj.ur.into(), &obj.obj))
+    }
+
+    /// Checks that the archive is consistent: the cell keys and the objects are sorted, the cell offsets
+    /// and object indices are in bounds, and every object is stored once in each of the cells it covers.
+    ///
+    /// This must be called on bytes that come from an untrusted source before querying them,
+    /// as `rkyv::check_archived_root` does not check these invariants.
+    pub fn validate(&self) -> Result<(), CorruptedGrid<AABBGridHandle>> {
+        if self.cell_size <= 0 {
+            return Err(CorruptedGrid::InvalidCellSize(self.cell_size));
+        }
+        if !valid_layout(&self.keys, &self.offsets, self.entries.len())
+            || !self.objects.windows(2).all(|w| w[0].handle < w[1].handle)
+            || self
+                .entries
+                .iter()
+                .any(|entry| entry.object as usize >= self.objects.len())
+        {
+            return Err(CorruptedGrid::InvalidLayout);
+        }
+
+        let mut errors = vec![];
+        let mut seen = fnv::FnvHashSet::default();
+        let mut n_entries = vec![0usize; self.objects.len()];
+
+        'cells: for (i, &key) in self.keys.iter().enumerate() {
+            let cell = unmorton(key);
+            for entry in &self.entries[self.offsets[i] as usize..self.offsets[i + 1] as usize] {
+                if errors.len() >= MAX_ERRORS {
+                    break 'cells;
+                }
+                let obj = &self.objects[entry.object as usize];
+                let handle = from_ffi(obj.handle);
+
+                if !seen.insert((cell, entry.object)) {
+                    errors.push(ValidationError::Duplicate { cell, handle });
+                    continue;
+                }
+
+                let ll = cell_id(self.cell_size, obj.ll);
+                let ur = cell_id(self.cell_size, obj.ur);
+                if !(ll.0..=ur.0).contains(&cell.0) || !(ll.1..=ur.1).contains(&cell.1) {
+                    errors.push(ValidationError::WrongCell { cell, handle });
+                    continue;
+                }
+                n_entries[entry.object as usize] += 1;
+
+                if entry.sing_cell != (ll == ur) {
+                    errors.push(ValidationError::WrongSingleCellFlag { cell, handle });
+                }
+            }
+        }
+
+        for (i, obj) in self.objects.iter().enumerate() {
+            if errors.len() >= MAX_ERRORS {
+                break;
+            }
+            let ll = cell_id(self.cell_size, obj.ll);
+            let ur = cell_id(self.cell_size, obj.ur);
+            // Same as AABBGrid::validate, the bounds can be huge so only the counts are compared
+            let n = n_entries[i];
+            if (n as u128) < cell_count(ll, ur) {
+                let missing = cell_range(ll, ur)
+                    .take(n + 1)
+                    .find(|&id| !seen.contains(&(id, i as u32)));
+                if let Some(cell) = missing {
+                    errors.push(ValidationError::MissingFromCell {
+                        cell,
+                        handle: from_ffi(obj.handle),
+                    });
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(CorruptedGrid::Invalid(errors))
+        }
+    }
+
+    fn cell(&self, id: CellIdx) -> &[ArchivedAABBEntry] {
+        match find_cell(&self.keys, id) {
+            Some(i) => &self.entries[self.offsets[i] as usize..self.offsets[i + 1] as usize],
+            None => &[],
+        }
+    }
+
+    /// Same as `AABBGrid::query`, returning the corners of the aabbs.
+    /// The objects are rebuilt as `AB` for the narrow phase, so the results are the same as on the live grid
+    /// when `AB` is its aabb type. Objects of another kind of shape are tested with their exact shapes.
+    pub fn query<'a, AB: ArchiveShape + 'a>(
+        &'a self,
+        aabb: AB,
+    ) -> impl Iterator<Item = (AABBGridHandle, AB::V2, AB::V2, &'a O::Archived)> + 'a {
+        let ll_id = cell_id(self.cell_size, aabb.ll());
+        let ur_id = cell_id(self.cell_size, aabb.ur());
+
+        cell_range(ll_id, ur_id).flat_map(move |id| {
+            self.cell(id).iter().filter_map(move |entry| {
+                let obj = &self.objects[entry.object as usize];
+                if !entry.sing_cell {
+                    // Only report the object in the first cell shared by the query and the object
+                    let obj_ll = cell_id(self.cell_size, obj.ll);
+                    if !first_shared_cell(ll_id, obj_ll, id) {
+                        return None;
+                    }
+                }
+                if !intersects_archived(&aabb, obj.ll, obj.ur, &obj.shape) {
+                    return None;
+                }
+                Some((from_ffi(obj.handle), obj.ll.into(), obj.ur.into(), &obj.obj))
+            })
+        })
+    }
+}
+
+/// Narrow phase of archived queries
+fn intersects_archived<AB: ArchiveShape>(
+    aabb: &AB,
+    ll: [f32; 2],
+    ur: [f32; 2],
+    shape: &ArchivedShapeData,
+) -> bool {
+    let data: ShapeData = shape.deserialize(&mut rkyv::Infallible).unwrap();
+    if let Some(obj) = AB::from_data(ll, ur, &data) {
+        return aabb.intersects(&obj);
+    }
+    let q = AnyShape::<[f32; 2]>::from_data(arr(aabb.ll()), arr(aabb.ur()), &aabb.to_data());
+    match (q, AnyShape::<[f32; 2]>::from_data(ll, ur, &data)) {
+        (Some(q), Some(obj)) => q.intersects_shape(&obj),
+        _ => false,
+    }
+}
@@ -0,0 +1,130 @@
+use crate::frozen::{morton, unmorton};
+use crate::storage::{cell_range, CellIdx};
+
+/// `BatchResults` stores the results of a batch of queries in a CSR-style layout:
+/// the hits of all queries are stored contiguously in `handles`, and `offsets[i]..offsets[i + 1]`
+/// is the range of the hits of the i-th query.
+///
+/// It is meant to be reused between batches so that it stops allocating once it is big enough.
+#[derive(Clone, Debug)]
+pub struct BatchResults<H> {
+    offsets: Vec<usize>,
+    handles: Vec<H>,
+    // Scratch buffers, kept around to avoid allocating on every batch
+    pairs: Vec<(u64, u32)>,
+    hits: Vec<H>,
+    hit_queries: Vec<u32>,
+    cursor: Vec<usize>,
+}
+
+impl<H> Default for BatchResults<H> {
+    fn default() -> Self {
+        Self {
+            offsets: vec![0],
+            handles: Vec::new(),
+            pairs: Vec::new(),
+            hits: Vec::new(),
+            hit_queries: Vec::new(),
+            cursor: Vec::new(),
+        }
+    }
+}
+
+impl<H: Copy> BatchResults<H> {
+    /// Creates an empty result buffer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of queries of the last batch
+    pub fn len(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    /// Checks if the last batch had any query
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the hits of the i-th query of the last batch.
+    /// Panics if i is out of bounds.
+    pub fn get(&self, i: usize) -> &[H] {
+        &self.handles[self.offsets[i]..self.offsets[i + 1]]
+    }
+
+    /// Iterate over the hits of each query of the last batch, in order
+    pub fn iter(&self) -> impl Iterator<Item = &[H]> + '_ {
+        self.offsets
+            .windows(2)
+            .map(move |w| &self.handles[w[0]..w[1]])
+    }
+
+    /// The offsets of each query's hits into `handles()`, with one extra trailing offset
+    pub fn offsets(&self) -> &[usize] {
+        &self.offsets
+    }
+
+    /// The hits of all the queries, stored contiguously
+    pub fn handles(&self) -> &[H] {
+        &self.handles
+    }
+
+    /// Runs a batch of queries, given by the range of cells each one covers.
+    /// The (cell, query) pairs are sorted by the Morton key of the cell, so every cell covered by the batch
+    /// is looked up once with `cell` and `test` is called on it for each query covering it,
+    /// pushing the hits of the query in that cell. The hits are then put in the order of the queries.
+    pub(crate) fn fill<'c, C: 'c>(
+        &mut self,
+        ranges: impl Iterator<Item = (CellIdx, CellIdx)>,
+        cell: impl Fn(CellIdx) -> Option<&'c C>,
+        mut test: impl FnMut(&'c C, CellIdx, usize, &mut Vec<H>),
+    ) {
+        self.pairs.clear();
+        let mut n = 0;
+        for (q, (ll, ur)) in ranges.enumerate() {
+            self.pairs
+                .extend(cell_range(ll, ur).map(|id| (morton(id), q as u32)));
+            n += 1;
+        }
+        self.pairs.sort_unstable_by_key(|&(key, _)| key);
+
+        self.hits.clear();
+        self.hit_queries.clear();
+        let mut i = 0;
+        while i < self.pairs.len() {
+            let key = self.pairs[i].0;
+            let mut end = i + 1;
+            while end < self.pairs.len() && self.pairs[end].0 == key {
+                end += 1;
+            }
+
+            let id = unmorton(key);
+            if let Some(c) = cell(id) {
+                for &(_, q) in &self.pairs[i..end] {
+                    test(c, id, q as usize, &mut self.hits);
+                    self.hit_queries.resize(self.hits.len(), q);
+                }
+            }
+            i = end;
+        }
+
+        // Counting sort of the hits by query
+        self.offsets.clear();
+        self.offsets.resize(n + 1, 0);
+        for &q in &self.hit_queries {
+            self.offsets[q as usize + 1] += 1;
+        }
+        for q in 0..n {
+            self.offsets[q + 1] += self.offsets[q];
+        }
+
+        self.cursor.clear();
+        self.cursor.extend_from_slice(&self.offsets[..n]);
+        self.handles.clear();
+        self.handles.extend_from_slice(&self.hits);
+        for (&q, &h) in self.hit_queries.iter().zip(&self.hits) {
+            self.handles[self.cursor[q as usize]] = h;
+            self.cursor[q as usize] += 1;
+        }
+    }
+}
@@ -1,6 +1,9 @@
-use crate::aabbgrid::AABBGridHandle;
+use crate::aabbgrid::{AABBGridHandle, AABBGridObjects, ObjectState as AABBObjectState};
+use crate::delta::Journal;
 use crate::grid::{GridHandle, GridObjects, ObjectState};
-use crate::Vec2;
+use crate::snapshot::History;
+use crate::storage::{cell_id, CellIdx};
+use crate::{Vec2, AABB};
 
 pub type CellObject<V2> = (GridHandle, V2);
 
@@ -26,6 +29,9 @@ impl<V2: Vec2> Default for GridCell<V2> {
 /// A single cell of the shape grid, can be empty
 pub struct AABBGridCell {
     pub objs: Vec<(AABBGridHandle, bool)>,
+    /// Missing from grids serialized before lazy updates existed
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) dirty: bool,
 }
 
 impl<V2: Vec2> GridCell<V2> {
@@ -33,6 +39,8 @@ impl<V2: Vec2> GridCell<V2> {
         &mut self,
         objects: &mut GridObjects<T, V2>,
         to_relocate: &mut Vec<CellObject<V2>>,
+        history: &mut History<T, V2>,
+        mut journal: Option<&mut Journal<T, V2>>,
     ) {
         if !self.dirty {
             return;
@@ -60,11 +68,89 @@ impl<V2: Vec2> GridCell<V2> {
                     self.objs.swap_remove(i);
                 }
                 ObjectState::Removed => {
+                    history.record_removed(objects, *obj_id);
                     objects.remove(*obj_id);
+                    if let Some(journal) = journal.as_deref_mut() {
+                        journal.removed(*obj_id);
+                    }
                     self.objs.swap_remove(i);
                 }
                 ObjectState::Unchanged => i += 1,
             }
         }
     }
+
+    /// Same as maintain() but only reads the objects, so that many cells can be maintained in parallel.
+    /// The handles of the objects that need their state applied are pushed to `changed`.
+    #[cfg(feature = "rayon")]
+    pub(crate) fn maintain_shared<T: Copy>(
+        &mut self,
+        objects: &GridObjects<T, V2>,
+        to_relocate: &mut Vec<CellObject<V2>>,
+        changed: &mut Vec<GridHandle>,
+    ) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+
+        let mut i = 0;
+        while i < self.objs.len() {
+            let (obj_id, obj_pos) = unsafe { self.objs.get_unchecked_mut(i) };
+
+            match objects[*obj_id].state {
+                ObjectState::NewPos(pos) => {
+                    changed.push(*obj_id);
+                    *obj_pos = pos;
+                    i += 1
+                }
+                ObjectState::Relocate(pos, _) => {
+                    changed.push(*obj_id);
+                    to_relocate.push((*obj_id, pos));
+                    self.objs.swap_remove(i);
+                }
+                ObjectState::Removed => {
+                    changed.push(*obj_id);
+                    self.objs.swap_remove(i);
+                }
+                ObjectState::Unchanged => i += 1,
+            }
+        }
+    }
+}
+
+impl AABBGridCell {
+    pub(crate) fn maintain<O: Copy, AB: AABB>(
+        &mut self,
+        id: CellIdx,
+        cell_size: i32,
+        objects: &AABBGridObjects<O, AB>,
+        to_relocate: &mut Vec<AABBGridHandle>,
+    ) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+
+        self.objs.retain(|(h, _)| {
+            let store_obj = &objects[*h];
+
+            let old_ll = cell_id(cell_size, store_obj.aabb.ll());
+            let keep = match store_obj.state {
+                AABBObjectState::Unchanged => return true,
+                AABBObjectState::NewAABB(aabb) => {
+                    old_ll == cell_id(cell_size, aabb.ll())
+                        && cell_id(cell_size, store_obj.aabb.ur()) == cell_id(cell_size, aabb.ur())
+                }
+                AABBObjectState::Removed => false,
+            };
+
+            // Only the lower left cell reports the object so it is relocated exactly once
+            if old_ll == id {
+                to_relocate.push(*h);
+            }
+
+            keep
+        });
+    }
 }
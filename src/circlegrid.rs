@@ -0,0 +1,215 @@
+use crate::aabbgrid::{AABBGridHandle, ObjectState};
+use crate::cell::AABBGridCell;
+use crate::storage::{first_shared_cell, SparseStorage};
+use crate::{AABBGrid, Vec2, AABB};
+
+pub use crate::shape::Circle;
+
+/// Handle of an object of a `CircleGrid`, returned by its _insert_ method.
+pub type CircleGridHandle = AABBGridHandle;
+
+/// `CircleGrid` is a spatial partitioning structure for circles of varying radii.
+///
+/// Like `AABBGrid`, each circle is stored in every cell its bounding box touches, so big circles are
+/// more expensive, but queries only look at the cells they cover and test the circles exactly.
+/// Like `Grid`, center and radius updates and removals are lazy and applied by maintain().
+///
+/// # Example
+/// ```rust
+/// use flat_spatial::CircleGrid;
+///
+/// let mut g: CircleGrid<(), [f32; 2]> = CircleGrid::new(10);
+/// let a = g.insert([0.0, 0.0], 3.0, ());
+/// let b = g.insert([5.0, 0.0], 1.0, ());
+///
+/// // The circles don't overlap, but both touch the query circle
+/// assert_eq!(g.overlaps(), vec![]);
+/// assert_eq!(g.query_around([3.5, 0.0], 1.0).count(), 2);
+///
+/// g.set_radius(b, 3.0);
+/// g.maintain();
+/// assert_eq!(g.overlaps().len(), 1);
+/// assert_eq!(g.query_point([5.5, 0.0]).map(|(h, _, _)| h).collect::<Vec<_>>(), vec![b]);
+/// ```
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "O: serde::Serialize, V2: serde::Serialize",
+        deserialize = "O: serde::Deserialize<'de>, V2: serde::Deserialize<'de>"
+    ))
+)]
+pub struct CircleGrid<O: Copy, V2: Vec2> {
+    grid: AABBGrid<O, Circle<V2>>,
+}
+
+impl<O: Copy, V2: Vec2> CircleGrid<O, V2> {
+    /// Creates an empty grid.
+    /// The cell size should be about the same magnitude as your queries size.
+    pub fn new(cell_size: i32) -> Self {
+        Self {
+            grid: AABBGrid::new(cell_size),
+        }
+    }
+
+    /// Creates an empty grid with room for `capacity` objects without reallocating.
+    pub fn with_capacity(cell_size: i32, capacity: usize) -> Self {
+        Self {
+            grid: AABBGrid::with_capacity(cell_size, capacity),
+        }
+    }
+
+    /// Inserts a new circle with an associated object.
+    /// Returns the unique and stable handle to be used with `get`
+    pub fn insert(&mut self, center: V2, radius: f32, obj: O) -> CircleGridHandle {
+        self.grid.insert(Circle::new(center, radius), obj)
+    }
+
+    /// Lazily sets the center of a circle (if it is not marked for deletion).
+    /// This won't be taken into account until maintain() is called.
+    pub fn set_position(&mut self, handle: CircleGridHandle, center: V2) {
+        if let Some(circle) = self.pending_circle(handle) {
+            self.grid
+                .set_aabb_lazy(handle, Circle::new(center, circle.radius));
+        }
+    }
+
+    /// Lazily sets the radius of a circle (if it is not marked for deletion).
+    /// This won't be taken into account until maintain() is called.
+    pub fn set_radius(&mut self, handle: CircleGridHandle, radius: f32) {
+        if let Some(circle) = self.pending_circle(handle) {
+            self.grid
+                .set_aabb_lazy(handle, Circle::new(circle.center, radius));
+        }
+    }
+
+    /// The circle an object will have after maintain(), None if it is removed
+    fn pending_circle(&self, handle: CircleGridHandle) -> Option<Circle<V2>> {
+        let obj = match self.grid.get(handle) {
+            Some(x) => x,
+            None => {
+                debug_assert!(false, "Object not in grid anymore");
+                return None;
+            }
+        };
+
+        match obj.state {
+            ObjectState::Unchanged => Some(obj.aabb),
+            ObjectState::NewAABB(circle) => Some(circle),
+            ObjectState::Removed => None,
+        }
+    }
+
+    /// Lazily removes an object from the grid.
+    /// This won't be taken into account until maintain() is called.
+    pub fn remove(&mut self, handle: CircleGridHandle) -> Option<O> {
+        self.grid.remove_lazy(handle)
+    }
+
+    /// Directly removes an object from the grid.
+    pub fn remove_maintain(&mut self, handle: CircleGridHandle) -> Option<O> {
+        self.grid.remove(handle)
+    }
+
+    /// Maintains the world, applying all the center and radius updates and removals.
+    pub fn maintain(&mut self) {
+        self.grid.maintain()
+    }
+
+    /// Same as maintain() but deterministic by sorting the relocations
+    pub fn maintain_deterministic(&mut self) {
+        self.grid.maintain_deterministic()
+    }
+
+    /// Clears the grid, returning the circles and their objects.
+    pub fn clear(&mut self) -> impl Iterator<Item = (Circle<V2>, O)> {
+        self.grid.clear()
+    }
+
+    /// Iterate over all handles
+    pub fn handles(&self) -> impl Iterator<Item = CircleGridHandle> + '_ {
+        self.grid.handles()
+    }
+
+    /// Iterate over all objects
+    pub fn objects(&self) -> impl Iterator<Item = &O> + '_ {
+        self.grid.objects()
+    }
+
+    /// Returns the circle and the associated object, using the handle.
+    pub fn get(&self, id: CircleGridHandle) -> Option<(Circle<V2>, &O)> {
+        self.grid.get(id).map(|x| (x.aabb, &x.obj))
+    }
+
+    /// Returns the circle and a mutable reference to the associated object, using the handle.
+    pub fn get_mut(&mut self, id: CircleGridHandle) -> Option<(Circle<V2>, &mut O)> {
+        self.grid.get_mut(id).map(|x| (x.aabb, &mut x.obj))
+    }
+
+    /// The underlying storage
+    pub fn storage(&self) -> &SparseStorage<AABBGridCell> {
+        self.grid.storage()
+    }
+
+    /// Queries for the circles overlapping the circle of the given center and radius.
+    pub fn query_around(
+        &self,
+        pos: V2,
+        radius: f32,
+    ) -> impl Iterator<Item = (CircleGridHandle, &Circle<V2>, &O)> + '_ {
+        self.grid.query(Circle::new(pos, radius))
+    }
+
+    /// Queries for the circles containing the given point.
+    pub fn query_point(
+        &self,
+        pos: V2,
+    ) -> impl Iterator<Item = (CircleGridHandle, &Circle<V2>, &O)> + '_ {
+        self.grid.query(Circle::new(pos, 0.0))
+    }
+
+    /// Visits every pair of overlapping circles once, in no particular order.
+    /// Pairs spanning multiple cells are only reported in the first cell they share.
+    pub fn overlaps_visitor(&self, mut visitor: impl FnMut(CircleGridHandle, CircleGridHandle)) {
+        let storage = self.grid.storage();
+        let objects = &self.grid.objects;
+
+        for (&id, cell) in storage.cells.iter() {
+            for (i, &(a, sing_a)) in cell.objs.iter().enumerate() {
+                let circle_a = &objects[a].aabb;
+                for &(b, sing_b) in &cell.objs[i + 1..] {
+                    let circle_b = &objects[b].aabb;
+                    if !circle_a.intersects(circle_b) {
+                        continue;
+                    }
+                    if !(sing_a || sing_b) {
+                        let ll_a = storage.cell_id(circle_a.ll());
+                        let ll_b = storage.cell_id(circle_b.ll());
+                        if !first_shared_cell(ll_a, ll_b, id) {
+                            continue;
+                        }
+                    }
+                    visitor(a, b);
+                }
+            }
+        }
+    }
+
+    /// Returns every pair of overlapping circles once, see `overlaps_visitor`.
+    pub fn overlaps(&self) -> Vec<(CircleGridHandle, CircleGridHandle)> {
+        let mut pairs = vec![];
+        self.overlaps_visitor(|a, b| pairs.push((a, b)));
+        pairs
+    }
+
+    /// Returns the number of objects
+    pub fn len(&self) -> usize {
+        self.grid.len()
+    }
+
+    /// Checks if the grid contains objects or not
+    pub fn is_empty(&self) -> bool {
+        self.grid.is_empty()
+    }
+}
@@ -0,0 +1,313 @@
+//! Compact, versioned serialization of grids, available with the `serde` feature.
+//!
+//! The default serde implementations of `Grid` and `AABBGrid` write out every cell, which roughly doubles the size
+//! of the data. The compact format only stores a format version header, the cell size and one record per object:
+//! its handle, its position or aabb, and its associated object. The cells are rebuilt on load.
+//!
+//! Pending position updates and removals are applied when writing, as if maintain() had been called.
+//! The handles of the objects are kept identical, but removed handles are not recorded, so later insertions
+//! do not necessarily get the same handles as in the original grid, and can reuse the handle of an object
+//! that was removed before writing.
+//!
+//! Rebuilding the handles takes time proportional to the largest handle index and to the number of times
+//! the slots of the handles were reused, so loading fails if either of them is more than `MAX_SLOTS_PER_OBJECT`
+//! times the number of objects.
+//!
+//! Use it with `#[serde(with = "flat_spatial::compact")]`, or call `serialize` and `deserialize` directly.
+//!
+//! ```rust
+//! use flat_spatial::Grid;
+//!
+//! #[derive(serde::Serialize, serde::Deserialize)]
+//! struct Level {
+//!     #[serde(with = "flat_spatial::compact")]
+//!     grid: Grid<u32, [f32; 2]>,
+//! }
+//!
+//! let mut grid = Grid::new(10);
+//! let h = grid.insert([3.0, 4.0], 42);
+//!
+//! let data = serde_json::to_string(&Level { grid }).unwrap();
+//! let level: Level = serde_json::from_str(&data).unwrap();
+//! assert_eq!(level.grid.get(h), Some(([3.0, 4.0], &42)));
+//! ```
+
+use crate::aabbgrid::{self, AABBGridHandle};
+use crate::grid::{self, GridHandle};
+use crate::{AABBGrid, Grid, Vec2, AABB};
+use serde::de::{Error, SeqAccess, Visitor};
+use serde::ser::SerializeTuple;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use slotmapd::{Key, SlotMap};
+use std::fmt::Formatter;
+use std::marker::PhantomData;
+
+/// Version of the compact format written by `serialize`.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Limit of the largest handle index, and of the number of times the slots of the handles were reused,
+/// per object of the compact data. Grids with fewer than 16 objects get the same limits as grids with 16.
+/// It bounds the time and memory taken to rebuild the handles by the size of the data.
+pub const MAX_SLOTS_PER_OBJECT: usize = 64;
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// Grids that can be serialized with the compact format.
+pub trait CompactFormat: private::Sealed + Sized {
+    #[doc(hidden)]
+    type Handle: Key;
+
+    /// Position or aabb of the objects
+    #[doc(hidden)]
+    type Shape;
+
+    #[doc(hidden)]
+    type Object: Copy;
+
+    #[doc(hidden)]
+    fn cell_size(&self) -> i32;
+
+    /// The objects that are not removed, with their up to date shape
+    #[doc(hidden)]
+    fn records(&self) -> Box<dyn Iterator<Item = Record<Self>> + '_>;
+
+    #[doc(hidden)]
+    fn from_records(cell_size: i32, records: Vec<Record<Self>>) -> Result<Self, String>;
+}
+
+/// What is written for each object: its handle, its position or aabb, and its associated object.
+pub type Record<G> = (
+    <G as CompactFormat>::Handle,
+    <G as CompactFormat>::Shape,
+    <G as CompactFormat>::Object,
+);
+
+impl<O: Copy, V2: Vec2> private::Sealed for Grid<O, V2> {}
+
+impl<O: Copy, V2: Vec2> CompactFormat for Grid<O, V2> {
+    type Handle = GridHandle;
+    type Shape = V2;
+    type Object = O;
+
+    fn cell_size(&self) -> i32 {
+        self.storage().cell_size()
+    }
+
+    fn records(&self) -> Box<dyn Iterator<Item = Record<Self>> + '_> {
+        Box::new(self.objects.iter().filter_map(|(h, x)| {
+            let pos = match x.state {
+                grid::ObjectState::Removed => return None,
+                grid::ObjectState::NewPos(pos) | grid::ObjectState::Relocate(pos, _) => pos,
+                grid::ObjectState::Unchanged => x.pos,
+            };
+            Some((h, pos, x.obj))
+        }))
+    }
+
+    fn from_records(cell_size: i32, records: Vec<Record<Self>>) -> Result<Self, String> {
+        let objects = with_keys(records.into_iter().map(|(h, pos, obj)| {
+            let obj = grid::StoreObject {
+                obj,
+                state: grid::ObjectState::Unchanged,
+                pos,
+                // Set by from_objects
+                cell_id: (0, 0),
+            };
+            (h, obj)
+        }))?;
+        Ok(Grid::from_objects(cell_size, objects))
+    }
+}
+
+impl<O: Copy, AB: AABB> private::Sealed for AABBGrid<O, AB> {}
+
+impl<O: Copy, AB: AABB> CompactFormat for AABBGrid<O, AB> {
+    type Handle = AABBGridHandle;
+    type Shape = AB;
+    type Object = O;
+
+    fn cell_size(&self) -> i32 {
+        self.storage().cell_size()
+    }
+
+    fn records(&self) -> Box<dyn Iterator<Item = Record<Self>> + '_> {
+        Box::new(self.objects.iter().filter_map(|(h, x)| {
+            let aabb = match x.state {
+                aabbgrid::ObjectState::Removed => return None,
+                aabbgrid::ObjectState::NewAABB(aabb) => aabb,
+                aabbgrid::ObjectState::Unchanged => x.aabb,
+            };
+            Some((h, aabb, x.obj))
+        }))
+    }
+
+    fn from_records(cell_size: i32, records: Vec<Record<Self>>) -> Result<Self, String> {
+        let objects = with_keys(records.into_iter().map(|(h, aabb, obj)| {
+            let obj = aabbgrid::StoreObject {
+                obj,
+                aabb,
+                state: aabbgrid::ObjectState::Unchanged,
+            };
+            (h, obj)
+        }))?;
+        Ok(AABBGrid::from_objects(cell_size, objects))
+    }
+}
+
+/// Builds a slot map where each value is stored at its given key.
+/// Slot maps can only insert in the next free slot, so the slots are allocated in order, and each of them is
+/// freed and filled again until its version matches the key.
+/// The slots that are not used are freed, the lowest one being the first to be reused.
+/// Fails if that would take more than `MAX_SLOTS_PER_OBJECT` slots or reuses per value.
+fn with_keys<K: Key, V: Copy>(
+    values: impl Iterator<Item = (K, V)>,
+) -> Result<SlotMap<K, V>, String> {
+    let idx = |k: K| k.data().as_ffi() as u32 as usize;
+    let version = |k: K| (k.data().as_ffi() >> 32) as u32;
+
+    let mut values: Vec<_> = values.collect();
+    values.sort_unstable_by_key(|&(k, _)| idx(k));
+
+    let mut map = SlotMap::with_key();
+    let (filler, n_slots) = match values.last() {
+        Some(&(k, v)) => (v, idx(k)),
+        None => return Ok(map),
+    };
+    if n_slots == u32::MAX as usize {
+        return Err("null handle".to_string());
+    }
+
+    let limit = values.len().max(16) * MAX_SLOTS_PER_OBJECT;
+    if n_slots > limit {
+        return Err(format!(
+            "handle index {} is too large for {} objects",
+            n_slots,
+            values.len()
+        ));
+    }
+    // Versions of keys are odd, and grow by 2 each time their slot is reused
+    let reuses: usize = values.iter().map(|&(k, _)| (version(k) / 2) as usize).sum();
+    if reuses > limit {
+        return Err(format!(
+            "handles were reused {} times, too many for {} objects",
+            reuses,
+            values.len()
+        ));
+    }
+
+    // slots[i] is the key of index i, the first slot of a slot map is never used
+    let mut slots = vec![None];
+    slots.extend((1..=n_slots).map(|_| Some(map.insert(filler))));
+    let mut used = vec![false; n_slots + 1];
+
+    for (k, v) in values {
+        let i = idx(k);
+        let mut cur = match slots[i] {
+            Some(cur) if !used[i] => cur,
+            _ => return Err(format!("invalid or duplicated handle {:?}", k.data())),
+        };
+        used[i] = true;
+
+        while cur != k {
+            map.remove(cur);
+            cur = map.insert(filler);
+        }
+        *map.get_mut(cur).unwrap() = v;
+    }
+
+    for i in (1..=n_slots).rev() {
+        if !used[i] {
+            map.remove(slots[i].unwrap());
+        }
+    }
+
+    Ok(map)
+}
+
+/// Serializes the records of a grid as a sequence, without collecting them
+struct Records<'a, G>(&'a G);
+
+impl<'a, G> Serialize for Records<'a, G>
+where
+    G: CompactFormat,
+    G::Handle: Serialize,
+    G::Shape: Serialize,
+    G::Object: Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.records())
+    }
+}
+
+/// Serializes a grid using the compact format.
+pub fn serialize<G, S>(grid: &G, serializer: S) -> Result<S::Ok, S::Error>
+where
+    G: CompactFormat,
+    G::Handle: Serialize,
+    G::Shape: Serialize,
+    G::Object: Serialize,
+    S: Serializer,
+{
+    let mut tup = serializer.serialize_tuple(3)?;
+    tup.serialize_element(&FORMAT_VERSION)?;
+    tup.serialize_element(&grid.cell_size())?;
+    tup.serialize_element(&Records(grid))?;
+    tup.end()
+}
+
+/// Deserializes a grid written with the compact format, rebuilding its cells.
+/// Fails if the format version is not supported, if the cell size is invalid, if a handle is duplicated
+/// or if the handles are beyond the limits of `MAX_SLOTS_PER_OBJECT`.
+pub fn deserialize<'de, G, D>(deserializer: D) -> Result<G, D::Error>
+where
+    G: CompactFormat,
+    Record<G>: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_tuple(3, CompactVisitor(PhantomData))
+}
+
+struct CompactVisitor<G>(PhantomData<G>);
+
+impl<'de, G> Visitor<'de> for CompactVisitor<G>
+where
+    G: CompactFormat,
+    Record<G>: Deserialize<'de>,
+{
+    type Value = G;
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "a compact grid")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<G, A::Error> {
+        // The version is checked first, as the rest of the layout depends on it
+        let version: u32 = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+        if version != FORMAT_VERSION {
+            return Err(A::Error::custom(format_args!(
+                "unsupported compact grid format version: {}",
+                version
+            )));
+        }
+
+        let cell_size: i32 = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::invalid_length(1, &self))?;
+        if cell_size <= 0 {
+            return Err(A::Error::custom(format_args!(
+                "invalid cell size: {}",
+                cell_size
+            )));
+        }
+
+        let records = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::invalid_length(2, &self))?;
+
+        G::from_records(cell_size, records).map_err(A::Error::custom)
+    }
+}
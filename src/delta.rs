@@ -0,0 +1,129 @@
+//! Deltas between successive states of a `Grid`, for example to replicate a world to remote clients.
+//!
+//! Once `Grid::set_delta_tracking` is enabled, the grid records its insertions and removals in the order they
+//! happen, and which objects moved or had their payload changed. `Grid::take_delta` returns these changes and
+//! starts a new delta, and `Grid::apply_delta` replays them on a copy of the grid.
+//!
+//! Replaying the insertions and removals in the same order gives back the same handles, as well as the same
+//! handle allocation state, so that the next deltas keep applying exactly. With the `serde` feature,
+//! `GridDelta` can be sent with any serde format.
+//!
+//! ```rust
+//! use flat_spatial::Grid;
+//!
+//! let mut server: Grid<u32, [f32; 2]> = Grid::new(10);
+//! server.set_delta_tracking(true);
+//! let mut client = server.clone();
+//!
+//! let a = server.insert([3.0, 4.0], 1);
+//! let b = server.insert([5.0, 5.0], 2);
+//! server.set_position(a, [30.0, 4.0]);
+//! server.remove(b);
+//! server.maintain();
+//!
+//! client.apply_delta(&server.take_delta()).unwrap();
+//! assert_eq!(client.get(a), Some(([30.0, 4.0], &1)));
+//! assert_eq!(client.get(b), None);
+//! // Both grids give the same handle to the next insertion
+//! assert_eq!(client.insert([0.0, 0.0], 3), server.insert([0.0, 0.0], 3));
+//! ```
+
+use crate::grid::GridHandle;
+use crate::Vec2;
+use std::fmt::{Display, Formatter};
+
+/// A change of the handle allocation of a grid. Their order matters, as it decides the next handles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DeltaEvent<O, V2: Vec2> {
+    /// An object was inserted with the given handle, position and payload
+    Inserted(GridHandle, V2, O),
+    /// An object was removed
+    Removed(GridHandle),
+    /// The grid was cleared
+    Cleared,
+    /// The grid was rebuilt with the given cell size
+    Rebuilt(i32),
+}
+
+/// The changes made to a grid between two calls to `Grid::take_delta`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GridDelta<O, V2: Vec2> {
+    /// Insertions and removals, in the order they happened
+    pub events: Vec<DeltaEvent<O, V2>>,
+    /// Live objects that moved or whose payload might have changed, sorted by handle, with their new position and payload
+    pub moved: Vec<(GridHandle, V2, O)>,
+}
+
+impl<O, V2: Vec2> Default for GridDelta<O, V2> {
+    fn default() -> Self {
+        Self {
+            events: Vec::new(),
+            moved: Vec::new(),
+        }
+    }
+}
+
+impl<O, V2: Vec2> GridDelta<O, V2> {
+    /// Checks if the delta contains any change
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty() && self.moved.is_empty()
+    }
+}
+
+/// Error returned by `Grid::apply_delta` when the grid is not in the state the delta was taken from.
+/// The grid is left partially updated and should be synchronized again from a full copy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeltaError {
+    /// An insertion gave a different handle than on the original grid
+    HandleMismatch {
+        expected: GridHandle,
+        got: GridHandle,
+    },
+    /// The delta refers to an object that does not exist
+    DeadHandle(GridHandle),
+}
+
+impl Display for DeltaError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeltaError::HandleMismatch { expected, got } => write!(
+                f,
+                "insertion gave handle {:?} instead of {:?}, the grid is out of sync",
+                got, expected
+            ),
+            DeltaError::DeadHandle(handle) => {
+                write!(
+                    f,
+                    "object {:?} does not exist, the grid is out of sync",
+                    handle
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for DeltaError {}
+
+/// Changes recorded by a grid since the last delta was taken.
+pub(crate) struct Journal<O, V2: Vec2> {
+    pub(crate) events: Vec<DeltaEvent<O, V2>>,
+    /// Objects that might have moved or changed, their final state is read when the delta is taken
+    pub(crate) moved: fnv::FnvHashSet<GridHandle>,
+}
+
+impl<O, V2: Vec2> Default for Journal<O, V2> {
+    fn default() -> Self {
+        Self {
+            events: Vec::new(),
+            moved: Default::default(),
+        }
+    }
+}
+
+impl<O, V2: Vec2> Journal<O, V2> {
+    pub(crate) fn removed(&mut self, handle: GridHandle) {
+        self.events.push(DeltaEvent::Removed(handle));
+    }
+}
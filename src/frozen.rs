@@ -0,0 +1,301 @@
+use crate::cell::{CellObject, GridCell};
+use crate::grid::ObjectState;
+use crate::grid::{GridHandle, GridObjects};
+use crate::storage::{cell_range, CellIdx, SparseStorage};
+use crate::validate::{CorruptedGrid, ValidationError};
+use crate::{Grid, Vec2};
+
+/// Spreads the 32 bits of x so that there is a zero bit between each of them.
+fn spread(x: u32) -> u64 {
+    let mut x = x as u64;
+    x = (x | (x << 16)) & 0x0000_FFFF_0000_FFFF;
+    x = (x | (x << 8)) & 0x00FF_00FF_00FF_00FF;
+    x = (x | (x << 4)) & 0x0F0F_0F0F_0F0F_0F0F;
+    x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+    x = (x | (x << 1)) & 0x5555_5555_5555_5555;
+    x
+}
+
+/// Inverse of `spread`, keeps the even bits of x.
+fn compact(x: u64) -> u32 {
+    let mut x = x & 0x5555_5555_5555_5555;
+    x = (x | (x >> 1)) & 0x3333_3333_3333_3333;
+    x = (x | (x >> 2)) & 0x0F0F_0F0F_0F0F_0F0F;
+    x = (x | (x >> 4)) & 0x00FF_00FF_00FF_00FF;
+    x = (x | (x >> 8)) & 0x0000_FFFF_0000_FFFF;
+    x = (x | (x >> 16)) & 0x0000_0000_FFFF_FFFF;
+    x as u32
+}
+
+/// Morton (Z-order) key of a cell, flipping the sign bit so that negative cells are ordered before positive ones.
+pub(crate) fn morton((x, y): CellIdx) -> u64 {
+    spread(x as u32 ^ 0x8000_0000) | (spread(y as u32 ^ 0x8000_0000) << 1)
+}
+
+/// Cell of a Morton key, inverse of `morton`.
+pub(crate) fn unmorton(key: u64) -> CellIdx {
+    (
+        (compact(key) ^ 0x8000_0000) as i32,
+        (compact(key >> 1) ^ 0x8000_0000) as i32,
+    )
+}
+
+/// Checks that `keys` are sorted and that `offsets` split `n_entries` entries into one range per key.
+pub(crate) fn valid_layout(keys: &[u64], offsets: &[u32], n_entries: usize) -> bool {
+    offsets.len() == keys.len() + 1
+        && offsets[0] == 0
+        && offsets[offsets.len() - 1] as usize == n_entries
+        && offsets.windows(2).all(|w| w[0] <= w[1])
+        && keys.windows(2).all(|w| w[0] < w[1])
+}
+
+/// `FrozenGrid` is an immutable snapshot of a `Grid`, obtained with `Grid::freeze`.
+///
+/// The cells are sorted by Morton order and all the object positions are stored contiguously
+/// in a single array with per-cell offsets, instead of the hash map of `Vec`s used by `Grid`.
+/// This is more compact and has no per-cell allocation, but looking up a cell is a binary search,
+/// so queries are not faster than with `Grid` (and can be a bit slower on large grids).
+/// Handles are the same as in the original grid, and `thaw` gives back a mutable grid.
+///
+/// # Example
+/// ```rust
+/// use flat_spatial::Grid;
+///
+/// let mut g: Grid<i32, [f32; 2]> = Grid::new(10);
+/// let a = g.insert([3.0, 3.0], 1);
+/// let _b = g.insert([12.0, -8.0], 2);
+///
+/// let frozen = g.freeze();
+/// let around: Vec<_> = frozen.query_around([2.0, 2.0], 5.0).map(|(id, _pos)| id).collect();
+/// assert_eq!(around, vec![a]);
+/// assert_eq!(frozen.get(a), Some(([3.0, 3.0], &1)));
+///
+/// let g = frozen.thaw();
+/// assert_eq!(g.get(a), Some(([3.0, 3.0], &1)));
+/// ```
+///
+/// ## Serialization
+/// With the `serde` feature, deserialized frozen grids are checked with `validate`, like `Grid`.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(
+        try_from = "FrozenGridRaw<O, V2>",
+        bound(deserialize = "O: Copy + serde::Deserialize<'de>, V2: serde::Deserialize<'de>")
+    )
+)]
+pub struct FrozenGrid<O, V2: Vec2> {
+    cell_size: i32,
+    /// Morton keys of the non-empty cells, sorted
+    pub(crate) keys: Vec<u64>,
+    /// offsets[i]..offsets[i + 1] is the range of the i-th cell in `entries`
+    pub(crate) offsets: Vec<u32>,
+    pub(crate) entries: Vec<CellObject<V2>>,
+    pub(crate) objects: GridObjects<O, V2>,
+}
+
+/// The unchecked serialized form of a frozen grid, turned into a FrozenGrid by validating it
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(rename = "FrozenGrid")]
+struct FrozenGridRaw<O, V2: Vec2> {
+    cell_size: i32,
+    keys: Vec<u64>,
+    offsets: Vec<u32>,
+    entries: Vec<CellObject<V2>>,
+    objects: GridObjects<O, V2>,
+}
+
+#[cfg(feature = "serde")]
+impl<O: Copy, V2: Vec2> TryFrom<FrozenGridRaw<O, V2>> for FrozenGrid<O, V2> {
+    type Error = CorruptedGrid<GridHandle>;
+
+    fn try_from(raw: FrozenGridRaw<O, V2>) -> Result<Self, Self::Error> {
+        let grid = Self {
+            cell_size: raw.cell_size,
+            keys: raw.keys,
+            offsets: raw.offsets,
+            entries: raw.entries,
+            objects: raw.objects,
+        };
+        grid.validate()?;
+
+        Ok(grid)
+    }
+}
+
+impl<O: Copy, V2: Vec2> FrozenGrid<O, V2> {
+    pub(crate) fn new(storage: SparseStorage<GridCell<V2>>, objects: GridObjects<O, V2>) -> Self {
+        let mut cells: Vec<_> = storage
+            .cells
+            .into_iter()
+            .filter(|(_, cell)| !cell.objs.is_empty())
+            .map(|(id, cell)| (morton(id), cell.objs))
+            .collect();
+        cells.sort_unstable_by_key(|(key, _)| *key);
+
+        let mut keys = Vec::with_capacity(cells.len());
+        let mut offsets = Vec::with_capacity(cells.len() + 1);
+        let mut entries = Vec::with_capacity(objects.len());
+
+        offsets.push(0);
+        for (key, objs) in cells {
+            keys.push(key);
+            entries.extend_from_slice(&objs);
+            offsets.push(entries.len() as u32);
+        }
+
+        Self {
+            cell_size: storage.cell_size,
+            keys,
+            offsets,
+            entries,
+            objects,
+        }
+    }
+
+    /// Turns the frozen grid back into a mutable `Grid`, keeping all the handles.
+    pub fn thaw(self) -> Grid<O, V2> {
+        Grid::from_objects(self.cell_size, self.objects)
+    }
+
+    pub fn cell_size(&self) -> i32 {
+        self.cell_size
+    }
+
+    /// Returns the objects of the cell with the given id, or an empty slice if there are none.
+    pub fn cell(&self, id: CellIdx) -> &[CellObject<V2>] {
+        match self.keys.binary_search(&morton(id)) {
+            Ok(i) => &self.entries[self.offsets[i] as usize..self.offsets[i + 1] as usize],
+            Err(_) => &[],
+        }
+    }
+
+    pub fn cell_id(&self, pos: V2) -> CellIdx {
+        crate::storage::cell_id(self.cell_size, pos)
+    }
+
+    /// Iterate over all handles
+    pub fn handles(&self) -> impl Iterator<Item = GridHandle> + '_ {
+        self.objects.keys()
+    }
+
+    /// Iterate over all objects
+    pub fn objects(&self) -> impl Iterator<Item = (V2, &O)> + '_ {
+        self.objects.values().map(|x| (x.pos, &x.obj))
+    }
+
+    /// Returns a reference to the associated object and its position, using the handle.
+    pub fn get(&self, id: GridHandle) -> Option<(V2, &O)> {
+        self.objects.get(id).map(|x| (x.pos, &x.obj))
+    }
+
+    /// Same as `Grid::query_around`
+    pub fn query_around(&self, pos: V2, radius: f32) -> impl Iterator<Item = CellObject<V2>> + '_ {
+        let ll = [pos.x() - radius, pos.y() - radius];
+        let ur = [pos.x() + radius, pos.y() + radius];
+
+        let radius2 = radius * radius;
+        self.query(ll.into(), ur.into())
+            .filter(move |(_, pos_obj)| {
+                let x = pos_obj.x() - pos.x();
+                let y = pos_obj.y() - pos.y();
+                x * x + y * y < radius2
+            })
+    }
+
+    /// Same as `Grid::query_aabb`
+    pub fn query_aabb(&self, ll_: V2, ur_: V2) -> impl Iterator<Item = CellObject<V2>> + '_ {
+        let ll = [ll_.x().min(ur_.x()), ll_.y().min(ur_.y())];
+        let ur = [ll_.x().max(ur_.x()), ll_.y().max(ur_.y())];
+
+        self.query(ll.into(), ur.into())
+            .filter(move |(_, pos_obj)| {
+                (ll[0]..=ur[0]).contains(&pos_obj.x()) && (ll[1]..=ur[1]).contains(&pos_obj.y())
+            })
+    }
+
+    /// Same as `Grid::query`
+    pub fn query(&self, ll: V2, ur: V2) -> impl Iterator<Item = CellObject<V2>> + '_ {
+        let ll_id = self.cell_id(ll);
+        let ur_id = self.cell_id(ur);
+
+        cell_range(ll_id, ur_id).flat_map(move |id| self.cell(id).iter().copied())
+    }
+
+    /// Checks the internal invariants of the frozen grid: the cell keys are sorted, the cell offsets
+    /// match the entries, and every object is stored once, in its cell, with its position.
+    /// A frozen grid built with `Grid::freeze` is always valid; this is meant for data coming from elsewhere.
+    pub fn validate(&self) -> Result<(), CorruptedGrid<GridHandle>> {
+        if self.cell_size <= 0 {
+            return Err(CorruptedGrid::InvalidCellSize(self.cell_size));
+        }
+        if !valid_layout(&self.keys, &self.offsets, self.entries.len()) {
+            return Err(CorruptedGrid::InvalidLayout);
+        }
+
+        let mut errors = vec![];
+        let mut seen = fnv::FnvHashSet::default();
+
+        for (i, &key) in self.keys.iter().enumerate() {
+            let cell = unmorton(key);
+            let entries = &self.entries[self.offsets[i] as usize..self.offsets[i + 1] as usize];
+            for &(handle, pos) in entries {
+                let obj = match self.objects.get(handle) {
+                    Some(x) => x,
+                    None => {
+                        errors.push(ValidationError::DeadHandle { cell, handle });
+                        continue;
+                    }
+                };
+
+                if !seen.insert(handle) {
+                    errors.push(ValidationError::Duplicate { cell, handle });
+                    continue;
+                }
+
+                if cell != obj.cell_id {
+                    errors.push(ValidationError::WrongCell { cell, handle });
+                    continue;
+                }
+
+                if pos.x().to_bits() != obj.pos.x().to_bits()
+                    || pos.y().to_bits() != obj.pos.y().to_bits()
+                {
+                    errors.push(ValidationError::PositionMismatch { cell, handle });
+                }
+            }
+        }
+
+        for (handle, obj) in self.objects.iter() {
+            if !matches!(obj.state, ObjectState::Unchanged) || self.cell_id(obj.pos) != obj.cell_id
+            {
+                errors.push(ValidationError::InvalidState { handle });
+            }
+
+            if !seen.contains(&handle) {
+                errors.push(ValidationError::MissingFromCell {
+                    cell: obj.cell_id,
+                    handle,
+                });
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(CorruptedGrid::Invalid(errors))
+        }
+    }
+
+    /// Returns the number of objects
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// Checks if the grid contains objects or not
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+}
@@ -1,8 +1,21 @@
+use crate::batch::BatchResults;
 use crate::cell::{CellObject, GridCell};
-use crate::storage::{cell_range, CellIdx, SparseStorage};
+use crate::delta::{DeltaError, DeltaEvent, GridDelta, Journal};
+use crate::frozen::FrozenGrid;
+use crate::heatmap::Heatmap;
+use crate::shape::PolygonQuery;
+use crate::snapshot::{GridSnapshot, History};
+use crate::stats::GridStats;
+use crate::storage::{
+    cell_bounds, cell_range, polygon_rows, suggest_cell_size, CellIdx, GroupByCell, SparseStorage,
+};
+use crate::validate::{ValidationError, MAX_ERRORS};
 use crate::Vec2;
 use slotmapd::{new_key_type, SlotMap};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
 use std::marker::PhantomData;
+use std::mem::size_of;
 
 pub type GridObjects<O, V2> = SlotMap<GridHandle, StoreObject<O, V2>>;
 
@@ -27,7 +40,7 @@ pub enum ObjectState<V2: Vec2> {
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StoreObject<O, V2: Vec2> {
     /// User-defined object to be associated with a value
-    obj: O,
+    pub(crate) obj: O,
     pub state: ObjectState<V2>,
     pub pos: V2,
     pub cell_id: CellIdx,
@@ -90,16 +103,82 @@ pub struct StoreObject<O, V2: Vec2> {
 /// assert_eq!(g.get(b).unwrap().1, &1); // We also check that b still has his data associated
 /// assert_eq!(g.get(a), None); // But that a doesn't exist anymore
 /// ```
-#[derive(Clone)]
+///
+/// ## Serialization
+/// With the `serde` feature, deserialized grids are checked with `validate` so that corrupted data is
+/// rejected with an error instead of breaking the grid's invariants.
+///
+/// ## Cloning
+/// Snapshots and delta tracking belong to the original grid: a clone has no snapshots and does not track deltas.
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(
+        try_from = "GridRaw<O, V2>",
+        bound(deserialize = "O: Copy + serde::Deserialize<'de>, V2: serde::Deserialize<'de>")
+    )
+)]
 pub struct Grid<O, V2: Vec2> {
     storage: SparseStorage<GridCell<V2>>,
-    objects: GridObjects<O, V2>,
+    pub(crate) objects: GridObjects<O, V2>,
     // Cache maintain vec to avoid allocating every time maintain is called
     to_relocate: Vec<CellObject<V2>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    history: History<O, V2>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    journal: Option<Journal<O, V2>>,
+    _phantom: PhantomData<V2>,
+}
+
+impl<O: Clone, V2: Vec2> Clone for Grid<O, V2> {
+    fn clone(&self) -> Self {
+        Self {
+            storage: self.storage.clone(),
+            objects: self.objects.clone(),
+            to_relocate: vec![],
+            history: Default::default(),
+            journal: None,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+/// The unchecked serialized form of a grid, turned into a Grid by validating it
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(rename = "Grid")]
+struct GridRaw<O, V2: Vec2> {
+    storage: SparseStorage<GridCell<V2>>,
+    objects: GridObjects<O, V2>,
+    #[allow(dead_code)]
+    to_relocate: Vec<CellObject<V2>>,
     _phantom: PhantomData<V2>,
 }
 
+#[cfg(feature = "serde")]
+impl<O: Copy, V2: Vec2> TryFrom<GridRaw<O, V2>> for Grid<O, V2> {
+    type Error = crate::validate::CorruptedGrid<GridHandle>;
+
+    fn try_from(raw: GridRaw<O, V2>) -> Result<Self, Self::Error> {
+        if raw.storage.cell_size <= 0 {
+            return Err(Self::Error::InvalidCellSize(raw.storage.cell_size));
+        }
+
+        // to_relocate is only a cache and is always empty outside of maintain
+        let grid = Self {
+            storage: raw.storage,
+            objects: raw.objects,
+            to_relocate: vec![],
+            history: Default::default(),
+            journal: None,
+            _phantom: raw._phantom,
+        };
+        grid.validate().map_err(Self::Error::Invalid)?;
+
+        Ok(grid)
+    }
+}
+
 impl<O: Copy, V2: Vec2> Grid<O, V2> {
     /// Creates an empty grid.   
     /// The cell size should be about the same magnitude as your queries size.
@@ -108,6 +187,116 @@ impl<O: Copy, V2: Vec2> Grid<O, V2> {
             storage: SparseStorage::new(cell_size),
             objects: SlotMap::with_key(),
             to_relocate: vec![],
+            history: Default::default(),
+            journal: None,
+            _phantom: Default::default(),
+        }
+    }
+
+    /// Creates an empty grid with room for `capacity` objects without reallocating.
+    /// The cell size should be about the same magnitude as your queries size.
+    pub fn with_capacity(cell_size: i32, capacity: usize) -> Self {
+        Self {
+            storage: SparseStorage::new(cell_size),
+            objects: SlotMap::with_capacity_and_key(capacity),
+            to_relocate: vec![],
+            history: Default::default(),
+            journal: None,
+            _phantom: Default::default(),
+        }
+    }
+
+    /// Creates a grid containing all the given objects, see `extend`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::Grid;
+    /// let g: Grid<i32, [f32; 2]> = Grid::from_iter(10, vec![([5.0, 3.0], 1), ([15.0, 3.0], 2)]);
+    /// assert_eq!(g.len(), 2);
+    /// ```
+    pub fn from_iter(cell_size: i32, objs: impl IntoIterator<Item = (V2, O)>) -> Self {
+        let mut g = Self::new(cell_size);
+        g.extend(objs);
+        g
+    }
+
+    /// Inserts many objects at once, much faster than calling insert for each of them.
+    /// The objects are counted per cell first so that each cell is allocated only once.
+    /// Returns the handles of the inserted objects, in the same order as the input.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::Grid;
+    /// let mut g: Grid<i32, [f32; 2]> = Grid::new(10);
+    /// let handles = g.extend(vec![([5.0, 3.0], 1), ([15.0, 3.0], 2)]);
+    /// assert_eq!(g.get(handles[1]), Some(([15.0, 3.0], &2)));
+    /// ```
+    pub fn extend(&mut self, objs: impl IntoIterator<Item = (V2, O)>) -> Vec<GridHandle> {
+        let objs = objs.into_iter();
+        let mut handles = Vec::with_capacity(objs.size_hint().0);
+        let mut by_cell = Vec::with_capacity(objs.size_hint().0);
+
+        self.objects.reserve(objs.size_hint().0);
+        for (pos, obj) in objs {
+            let cell_id = self.storage.cell_id(pos);
+            let handle = self.objects.insert(StoreObject {
+                obj,
+                state: ObjectState::Unchanged,
+                pos,
+                cell_id,
+            });
+            self.history.record_inserted(&self.objects, handle);
+            if let Some(journal) = &mut self.journal {
+                journal.events.push(DeltaEvent::Inserted(handle, pos, obj));
+            }
+            by_cell.push((cell_id, (handles.len(), pos)));
+            handles.push(handle);
+        }
+
+        by_cell.sort_unstable_by_key(|&(cell_id, (i, _))| (cell_id, i));
+
+        for group in GroupByCell(&by_cell) {
+            self.history.record(&self.storage, group[0].0);
+            let cell = self.storage.cell_mut_unchecked(group[0].0);
+            cell.objs.reserve_exact(group.len());
+            cell.objs
+                .extend(group.iter().map(|&(_, (i, pos))| (handles[i], pos)));
+        }
+
+        handles
+    }
+
+    /// Rebuilds a grid from its objects, applying any pending position update or removal.
+    /// Handles are kept as is.
+    pub(crate) fn from_objects(cell_size: i32, mut objects: GridObjects<O, V2>) -> Self {
+        let mut storage: SparseStorage<GridCell<V2>> = SparseStorage::new(cell_size);
+
+        objects.retain(|_, obj| !matches!(obj.state, ObjectState::Removed));
+
+        let mut by_cell = Vec::with_capacity(objects.len());
+        for (handle, obj) in objects.iter_mut() {
+            if let ObjectState::NewPos(pos) | ObjectState::Relocate(pos, _) = obj.state {
+                obj.pos = pos;
+            }
+            obj.state = ObjectState::Unchanged;
+            obj.cell_id = storage.cell_id(obj.pos);
+            by_cell.push((obj.cell_id, (handle, obj.pos)));
+        }
+
+        by_cell.sort_unstable_by_key(|&(cell_id, (handle, _))| (cell_id, handle));
+
+        for group in GroupByCell(&by_cell) {
+            let cell = storage.cell_mut_unchecked(group[0].0);
+            cell.objs.reserve_exact(group.len());
+            cell.objs.extend(group.iter().map(|&(_, obj)| obj));
+        }
+
+        Self {
+            storage,
+            objects,
+            to_relocate: vec![],
+            history: Default::default(),
+            journal: None,
             _phantom: Default::default(),
         }
     }
@@ -115,20 +304,27 @@ impl<O: Copy, V2: Vec2> Grid<O, V2> {
     /// Inserts a new object with a position and an associated object
     /// Returns the unique and stable handle to be used with `get_obj`
     pub fn insert(&mut self, pos: V2, obj: O) -> GridHandle {
-        let (cell_id, cell) = self.storage.cell_mut(pos);
+        let cell_id = self.storage.cell_id(pos);
+        self.history.record(&self.storage, cell_id);
+        let cell = self.storage.cell_mut_unchecked(cell_id);
         let handle = self.objects.insert(StoreObject {
             obj,
             state: ObjectState::Unchanged,
             pos,
             cell_id,
         });
+        self.history.record_inserted(&self.objects, handle);
         cell.objs.push((handle, pos));
+        if let Some(journal) = &mut self.journal {
+            journal.events.push(DeltaEvent::Inserted(handle, pos, obj));
+        }
         handle
     }
 
     /// Lazily sets the position of an object (if it is not marked for deletion).
     /// This won't be taken into account until maintain() is called.
     pub fn set_position(&mut self, handle: GridHandle, pos: V2) {
+        self.history.record_object(&self.objects, handle);
         let obj = match self.objects.get_mut(handle) {
             Some(x) => x,
             None => {
@@ -148,7 +344,71 @@ impl<O: Copy, V2: Vec2> Grid<O, V2> {
             ObjectState::Relocate(pos, target_id)
         };
 
+        self.history.record(&self.storage, obj.cell_id);
         self.storage.cell_mut_unchecked(obj.cell_id).dirty = true;
+        if let Some(journal) = &mut self.journal {
+            journal.moved.insert(handle);
+        }
+    }
+
+    /// Directly sets the position of an object (if it is not marked for deletion).
+    /// This is equivalent to set_position() then maintain() for a single object, but runs in
+    /// O(k) where k is the number of objects in the old cell.
+    /// Any pending lazy position update for this object is overridden.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::Grid;
+    /// let mut g: Grid<(), [f32; 2]> = Grid::new(10);
+    /// let h = g.insert([5.0, 3.0], ());
+    /// g.set_position_maintain(h, [25.0, 3.0]);
+    ///
+    /// assert_eq!(g.get(h), Some(([25.0, 3.0], &())));
+    /// assert_eq!(g.query_around([25.0, 3.0], 1.0).next(), Some((h, [25.0, 3.0])));
+    /// ```
+    pub fn set_position_maintain(&mut self, handle: GridHandle, pos: V2) {
+        self.history.record_object(&self.objects, handle);
+        let obj = match self.objects.get_mut(handle) {
+            Some(x) => x,
+            None => {
+                debug_assert!(false, "Object not in grid anymore");
+                return;
+            }
+        };
+
+        if matches!(obj.state, ObjectState::Removed) {
+            return;
+        }
+
+        let old_id = obj.cell_id;
+        let target_id = self.storage.cell_id(pos);
+
+        obj.state = ObjectState::Unchanged;
+        obj.pos = pos;
+        obj.cell_id = target_id;
+
+        if let Some(journal) = &mut self.journal {
+            journal.moved.insert(handle);
+        }
+
+        self.history.record(&self.storage, old_id);
+        self.history.record(&self.storage, target_id);
+        let cell = self.storage.cell_mut_unchecked(old_id);
+        let i = match cell.objs.iter().position(|(h, _)| *h == handle) {
+            Some(x) => x,
+            None => return,
+        };
+
+        if target_id == old_id {
+            cell.objs[i].1 = pos;
+            return;
+        }
+
+        cell.objs.swap_remove(i);
+        self.storage
+            .cell_mut_unchecked(target_id)
+            .objs
+            .push((handle, pos));
     }
 
     /// Lazily removes an object from the grid.
@@ -162,9 +422,11 @@ impl<O: Copy, V2: Vec2> Grid<O, V2> {
     /// g.remove(h);
     /// ```
     pub fn remove(&mut self, handle: GridHandle) -> Option<O> {
+        self.history.record_object(&self.objects, handle);
         let obj = self.objects.get_mut(handle)?;
 
         obj.state = ObjectState::Removed;
+        self.history.record(&self.storage, obj.cell_id);
         self.storage.cell_mut_unchecked(obj.cell_id).dirty = true;
 
         Some(obj.obj)
@@ -181,8 +443,13 @@ impl<O: Copy, V2: Vec2> Grid<O, V2> {
     /// g.remove(h);
     /// ```
     pub fn remove_maintain(&mut self, handle: GridHandle) -> Option<O> {
+        self.history.record_removed(&self.objects, handle);
         let obj = self.objects.remove(handle)?;
 
+        if let Some(journal) = &mut self.journal {
+            journal.removed(handle);
+        }
+        self.history.record(&self.storage, obj.cell_id);
         let cell = self.storage.cell_mut_unchecked(obj.cell_id);
 
         for i in 0..cell.objs.len() {
@@ -198,9 +465,16 @@ impl<O: Copy, V2: Vec2> Grid<O, V2> {
     /// Clear all objects from the grid.
     /// Returns the objects and their positions.
     pub fn clear(&mut self) -> impl Iterator<Item = (V2, O)> {
+        self.history.record_cleared(&self.objects);
         let objects = std::mem::take(&mut self.objects);
-        self.storage = SparseStorage::new(self.storage.cell_size());
+        let empty = SparseStorage::new(self.storage.cell_size());
+        let old = std::mem::replace(&mut self.storage, empty);
+        self.history.record_storage(old);
         self.to_relocate.clear();
+        if let Some(journal) = &mut self.journal {
+            journal.events.push(DeltaEvent::Cleared);
+            journal.moved.clear();
+        }
         objects.into_iter().map(|(_, x)| (x.pos, x.obj))
     }
 
@@ -227,16 +501,21 @@ impl<O: Copy, V2: Vec2> Grid<O, V2> {
             storage,
             objects,
             to_relocate,
+            history,
+            journal,
             ..
         } = self;
 
+        history.record_maintain(storage, objects);
         storage.modify(|cell| {
-            cell.maintain(objects, to_relocate);
+            cell.maintain(objects, to_relocate, history, journal.as_mut());
             cell.objs.is_empty()
         });
 
         for (handle, pos) in to_relocate.drain(..) {
-            storage.cell_mut(pos).1.objs.push((handle, pos));
+            let id = storage.cell_id(pos);
+            history.record(storage, id);
+            storage.cell_mut_unchecked(id).objs.push((handle, pos));
         }
     }
 
@@ -246,21 +525,153 @@ impl<O: Copy, V2: Vec2> Grid<O, V2> {
             storage,
             objects,
             to_relocate,
+            history,
+            journal,
             ..
         } = self;
 
+        history.record_maintain(storage, objects);
         storage.modify(|cell| {
-            cell.maintain(objects, to_relocate);
+            cell.maintain(objects, to_relocate, history, journal.as_mut());
             cell.objs.is_empty()
         });
 
         to_relocate.sort_unstable_by_key(|obj| obj.0);
 
         for (handle, pos) in to_relocate.drain(..) {
-            storage.cell_mut(pos).1.objs.push((handle, pos));
+            let id = storage.cell_id(pos);
+            history.record(storage, id);
+            storage.cell_mut_unchecked(id).objs.push((handle, pos));
+        }
+    }
+
+    /// Takes a snapshot of the grid that can be restored later with `restore`, for example for rollback netcode.
+    ///
+    /// This is much cheaper than cloning the grid: the cells and the objects are copy-on-write, only the ones
+    /// modified after the snapshot are saved, and insertions and removals are logged.
+    /// Taking a snapshot while there are none copies the objects once, to keep their handle allocation state.
+    /// Restoring over insertions or removals replays them on this copy, so it takes linear time.
+    /// Snapshots are kept by the grid until they are forgotten, so call `forget_snapshots_before` regularly.
+    /// Nothing is recorded while there are no snapshots.
+    ///
+    /// Restoring is exact, so use maintain_deterministic if the simulation must replay identically.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::Grid;
+    /// let mut g: Grid<(), [f32; 2]> = Grid::new(10);
+    /// let a = g.insert([5.0, 3.0], ());
+    /// let snap = g.snapshot();
+    ///
+    /// g.set_position(a, [25.0, 3.0]);
+    /// g.maintain();
+    /// let b = g.insert([1.0, 1.0], ());
+    ///
+    /// assert!(g.restore(snap));
+    /// assert_eq!(g.get(a), Some(([5.0, 3.0], &())));
+    /// assert_eq!(g.get(b), None);
+    /// // Handle allocation is restored too, so the next insertion gives the same handle again
+    /// assert_eq!(g.insert([1.0, 1.0], ()), b);
+    /// ```
+    pub fn snapshot(&mut self) -> GridSnapshot {
+        self.history.snapshot(&self.objects)
+    }
+
+    /// Brings the grid back to the state it had when the snapshot was taken: positions, pending updates,
+    /// cells and handle allocation are all restored exactly.
+    /// The snapshots taken after it are forgotten, but the snapshot itself can be restored again.
+    ///
+    /// Returns false and leaves the grid untouched if the snapshot was forgotten.
+    /// Restoring is not recorded by delta tracking, clients have to be synchronized again from a full copy.
+    pub fn restore(&mut self, snapshot: GridSnapshot) -> bool {
+        self.history
+            .restore(snapshot, &mut self.storage, &mut self.objects)
+    }
+
+    /// Forgets the snapshots taken before the given one, freeing the changes recorded for them.
+    pub fn forget_snapshots_before(&mut self, snapshot: GridSnapshot) {
+        self.history.forget_before(Some(snapshot));
+    }
+
+    /// Forgets all the snapshots, which stops recording changes.
+    pub fn clear_snapshots(&mut self) {
+        self.history.forget_before(None);
+    }
+
+    /// Starts or stops recording the changes made to the grid, see the `delta` module.
+    /// Stopping drops the changes that were not taken yet.
+    pub fn set_delta_tracking(&mut self, enabled: bool) {
+        match (enabled, self.journal.is_some()) {
+            (true, false) => self.journal = Some(Journal::default()),
+            (false, true) => self.journal = None,
+            _ => {}
+        }
+    }
+
+    /// Returns the changes recorded since the last call, or since tracking was enabled.
+    /// Objects with a pending position update are only reported once maintain() has been called,
+    /// so it is best to take the delta right after maintaining the grid.
+    pub fn take_delta(&mut self) -> GridDelta<O, V2> {
+        let journal = match &mut self.journal {
+            Some(x) => x,
+            None => return GridDelta::default(),
+        };
+
+        let objects = &self.objects;
+        let mut moved = Vec::with_capacity(journal.moved.len());
+        journal.moved.retain(|&handle| match objects.get(handle) {
+            Some(obj) if matches!(obj.state, ObjectState::Unchanged) => {
+                moved.push((handle, obj.pos, obj.obj));
+                false
+            }
+            Some(_) => true,
+            None => false,
+        });
+        moved.sort_unstable_by_key(|&(handle, _, _)| handle);
+
+        GridDelta {
+            events: std::mem::take(&mut journal.events),
+            moved,
         }
     }
 
+    /// Applies a delta taken from another grid with `take_delta`.
+    /// This grid must be in the state the other grid was in when the previous delta was taken
+    /// (or when tracking was enabled), for example by being a clone of it.
+    ///
+    /// Afterwards, both grids have the same objects with the same handles, positions and payloads,
+    /// and give the same handles to the next insertions. The order of the objects inside a cell might differ.
+    pub fn apply_delta(&mut self, delta: &GridDelta<O, V2>) -> Result<(), DeltaError> {
+        for event in &delta.events {
+            match *event {
+                DeltaEvent::Inserted(expected, pos, obj) => {
+                    let got = self.insert(pos, obj);
+                    if got != expected {
+                        return Err(DeltaError::HandleMismatch { expected, got });
+                    }
+                }
+                DeltaEvent::Removed(handle) => {
+                    self.remove_maintain(handle)
+                        .ok_or(DeltaError::DeadHandle(handle))?;
+                }
+                DeltaEvent::Cleared => {
+                    let _ = self.clear();
+                }
+                DeltaEvent::Rebuilt(cell_size) => self.rebuild(cell_size),
+            }
+        }
+
+        for &(handle, pos, obj) in &delta.moved {
+            *self
+                .get_mut(handle)
+                .ok_or(DeltaError::DeadHandle(handle))?
+                .1 = obj;
+            self.set_position_maintain(handle, pos);
+        }
+
+        Ok(())
+    }
+
     /// Iterate over all handles
     pub fn handles(&self) -> impl Iterator<Item = GridHandle> + '_ {
         self.objects.keys()
@@ -295,7 +706,12 @@ impl<O: Copy, V2: Vec2> Grid<O, V2> {
     /// assert_eq!(g.get(h).unwrap().1, &56);
     /// ```    
     pub fn get_mut(&mut self, id: GridHandle) -> Option<(V2, &mut O)> {
-        self.objects.get_mut(id).map(|x| (x.pos, &mut x.obj))
+        self.history.record_object(&self.objects, id);
+        let obj = self.objects.get_mut(id)?;
+        if let Some(journal) = &mut self.journal {
+            journal.moved.insert(id);
+        }
+        Some((obj.pos, &mut obj.obj))
     }
 
     /// The underlying storage
@@ -303,6 +719,136 @@ impl<O: Copy, V2: Vec2> Grid<O, V2> {
         &self.storage
     }
 
+    /// Rebuilds the grid with a new cell size, keeping all the handles.
+    /// Pending position updates and removals are applied.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::Grid;
+    /// let mut g: Grid<(), [f32; 2]> = Grid::new(10);
+    /// let h = g.insert([5.0, 3.0], ());
+    /// g.rebuild(100);
+    ///
+    /// assert_eq!(g.storage().cell_size(), 100);
+    /// assert_eq!(g.query_around([5.0, 3.0], 1.0).next(), Some((h, [5.0, 3.0])));
+    /// ```
+    pub fn rebuild(&mut self, cell_size: i32) {
+        if let Some(journal) = &mut self.journal {
+            // from_objects removes the pending removals in slot order
+            for (handle, obj) in self.objects.iter() {
+                if matches!(obj.state, ObjectState::Removed) {
+                    journal.removed(handle);
+                }
+            }
+            journal.events.push(DeltaEvent::Rebuilt(cell_size));
+        }
+        // from_objects modifies all the objects and removes the pending removals in slot order
+        self.history.record_all_objects(&self.objects);
+        if self.history.is_recording() {
+            let removed: Vec<_> = self
+                .objects
+                .iter()
+                .filter(|(_, obj)| matches!(obj.state, ObjectState::Removed))
+                .map(|(handle, _)| handle)
+                .collect();
+            for handle in removed {
+                self.history.record_removed(&self.objects, handle);
+            }
+        }
+        let objects = std::mem::take(&mut self.objects);
+        let rebuilt = Self::from_objects(cell_size, objects);
+        self.objects = rebuilt.objects;
+        let old = std::mem::replace(&mut self.storage, rebuilt.storage);
+        self.history.record_storage(old);
+    }
+
+    /// Suggests a cell size for this grid, aiming for about 16 objects per non-empty cell.
+    /// `query_extents` is an optional sample of the widths of the queries that are run on this grid
+    /// (twice the radius for query_around), pass an empty slice if you don't have one.
+    /// The result can then be given to `rebuild`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::Grid;
+    /// let mut g: Grid<(), [f32; 2]> = Grid::new(10);
+    /// for i in 0..400 {
+    ///     g.insert([(i % 20) as f32 * 0.5, (i / 20) as f32 * 0.5], ());
+    /// }
+    /// // All the objects are in the same cell, so it should be smaller
+    /// assert!(g.suggest_cell_size(&[]) < 10);
+    /// ```
+    pub fn suggest_cell_size(&self, query_extents: &[f32]) -> i32 {
+        suggest_cell_size(
+            self.storage.cell_size(),
+            self.storage.cells.len(),
+            self.storage.cells.values().map(|x| x.objs.len()).sum(),
+            query_extents,
+        )
+    }
+
+    /// Returns occupancy statistics about the grid, to check if the cell size is adapted to the problem.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::Grid;
+    /// let mut g: Grid<(), [f32; 2]> = Grid::new(10);
+    /// g.insert([5.0, 3.0], ());
+    /// g.insert([6.0, 3.0], ());
+    /// g.insert([15.0, 3.0], ());
+    ///
+    /// let stats = g.stats();
+    /// assert_eq!(stats.cells, 2);
+    /// assert_eq!(stats.max_occupancy, 2);
+    /// println!("{}", stats);
+    /// ```
+    pub fn stats(&self) -> GridStats {
+        let cells = &self.storage.cells;
+        let mut stats = GridStats::from_cells(cells.values().map(|x| (x.objs.len(), x.dirty)));
+
+        stats.objects = self.objects.len();
+        stats.memory_bytes = size_of::<Self>()
+            + cells.capacity() * size_of::<(CellIdx, GridCell<V2>)>()
+            + cells
+                .values()
+                .map(|x| x.objs.capacity() * size_of::<CellObject<V2>>())
+                .sum::<usize>()
+            + self.objects.capacity() * (size_of::<StoreObject<O, V2>>() + size_of::<u32>())
+            + self.to_relocate.capacity() * size_of::<CellObject<V2>>();
+
+        stats
+    }
+
+    /// Rasterizes the number of objects per cell over the rectangle defined by lower left (ll) and upper right (ur),
+    /// one pixel per cell. See `Heatmap` to export it as an image.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::Grid;
+    /// let mut g: Grid<(), [f32; 2]> = Grid::new(10);
+    /// g.insert([5.0, 3.0], ());
+    /// g.insert([6.0, 3.0], ());
+    /// g.insert([15.0, 3.0], ());
+    ///
+    /// let heatmap = g.heatmap([0.0, 0.0], [29.0, 9.0]);
+    /// assert_eq!(heatmap.counts, vec![2, 1, 0]);
+    /// let _image = heatmap.to_pgm();
+    /// ```
+    pub fn heatmap(&self, ll: V2, ur: V2) -> Heatmap {
+        let ll_id = self.storage.cell_id(ll);
+        let ur_id = self.storage.cell_id(ur);
+
+        self.storage
+            .rasterize(ll_id, ur_id, |cell| cell.objs.len() as u32)
+    }
+
+    /// Turns the grid into an immutable `FrozenGrid`, which has a more compact memory layout.
+    /// Pending position updates and removals are applied first, and handles are kept as is.
+    /// Use `FrozenGrid::thaw` to get back a mutable grid.
+    pub fn freeze(mut self) -> FrozenGrid<O, V2> {
+        self.maintain_deterministic();
+        FrozenGrid::new(self.storage, self.objects)
+    }
+
     pub fn query_around(&self, pos: V2, radius: f32) -> impl Iterator<Item = CellObject<V2>> + '_ {
         let ll = [pos.x() - radius, pos.y() - radius];
         let ur = [pos.x() + radius, pos.y() + radius];
@@ -316,6 +862,193 @@ impl<O: Copy, V2: Vec2> Grid<O, V2> {
             })
     }
 
+    /// Queries for the objects at a distance between `min_radius` (included) and `max_radius` (excluded) of `pos`.
+    /// Cells that are entirely closer than `min_radius` or farther than `max_radius` are skipped.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::Grid;
+    ///
+    /// let mut g: Grid<(), [f32; 2]> = Grid::new(10);
+    /// let a = g.insert([1.0, 1.0], ());
+    /// let b = g.insert([20.0, 0.0], ());
+    ///
+    /// let ring: Vec<_> = g.query_annulus([0.0, 0.0], 10.0, 30.0).map(|(id, _pos)| id).collect();
+    ///
+    /// assert_eq!(ring, vec![b]);
+    /// ```
+    pub fn query_annulus(
+        &self,
+        pos: V2,
+        min_radius: f32,
+        max_radius: f32,
+    ) -> impl Iterator<Item = CellObject<V2>> + '_ {
+        let center = [pos.x(), pos.y()];
+        let ll_id = self
+            .storage
+            .cell_id(V2::from([center[0] - max_radius, center[1] - max_radius]));
+        let ur_id = self
+            .storage
+            .cell_id(V2::from([center[0] + max_radius, center[1] + max_radius]));
+
+        let min2 = min_radius * min_radius;
+        let max2 = max_radius * max_radius;
+        let cell_size = self.storage.cell_size();
+
+        cell_range(ll_id, ur_id)
+            .filter(move |&id| {
+                let (ll, ur) = cell_bounds(cell_size, id);
+                let mut nearest = 0.0;
+                let mut farthest = 0.0;
+                for i in 0..2 {
+                    let near = (ll[i] - center[i]).max(center[i] - ur[i]).max(0.0);
+                    let far = (center[i] - ll[i]).max(ur[i] - center[i]);
+                    nearest += near * near;
+                    farthest += far * far;
+                }
+                nearest < max2 && farthest >= min2
+            })
+            .flat_map(move |id| self.storage.cell(id))
+            .flat_map(|x| x.objs.iter().copied())
+            .filter(move |(_, pos_obj)| {
+                let x = pos_obj.x() - center[0];
+                let y = pos_obj.y() - center[1];
+                let d2 = x * x + y * y;
+                min2 <= d2 && d2 < max2
+            })
+    }
+
+    /// Queries for the objects closer than `radius` to `pos` and within `half_angle` radians of the direction `dir`,
+    /// such as a vision cone. `dir` doesn't need to be normalized.
+    /// Cells that are entirely outside the cone are skipped.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::Grid;
+    ///
+    /// let mut g: Grid<(), [f32; 2]> = Grid::new(10);
+    /// let a = g.insert([10.0, 2.0], ());
+    /// let b = g.insert([0.0, 10.0], ());
+    ///
+    /// let seen: Vec<_> = g.query_cone([0.0, 0.0], [1.0, 0.0], 0.5, 20.0).map(|(id, _pos)| id).collect();
+    ///
+    /// assert_eq!(seen, vec![a]);
+    /// ```
+    pub fn query_cone(
+        &self,
+        pos: V2,
+        dir: V2,
+        half_angle: f32,
+        radius: f32,
+    ) -> impl Iterator<Item = CellObject<V2>> + '_ {
+        let center = [pos.x(), pos.y()];
+        let len = (dir.x() * dir.x() + dir.y() * dir.y()).sqrt();
+        let d = if len > 0.0 {
+            [dir.x() / len, dir.y() / len]
+        } else {
+            [1.0, 0.0]
+        };
+        let cos = half_angle.cos();
+
+        // Boundary rays of the cone, a point is inside the wedge if it is left of `right` and right of `left`
+        // (or either of them if the cone is wider than a half plane)
+        let (sin_a, cos_a) = half_angle.sin_cos();
+        let left = [d[0] * cos_a - d[1] * sin_a, d[0] * sin_a + d[1] * cos_a];
+        let right = [d[0] * cos_a + d[1] * sin_a, d[1] * cos_a - d[0] * sin_a];
+        let convex = half_angle <= std::f32::consts::FRAC_PI_2;
+        let full = half_angle >= std::f32::consts::PI;
+
+        let cell_size = self.storage.cell_size();
+        let ll_id = self
+            .storage
+            .cell_id(V2::from([center[0] - radius, center[1] - radius]));
+        let ur_id = self
+            .storage
+            .cell_id(V2::from([center[0] + radius, center[1] + radius]));
+        let radius2 = radius * radius;
+
+        cell_range(ll_id, ur_id)
+            .filter(move |&id| {
+                let (ll, ur) = cell_bounds(cell_size, id);
+                let x = (ll[0] - center[0]).max(center[0] - ur[0]).max(0.0);
+                let y = (ll[1] - center[1]).max(center[1] - ur[1]).max(0.0);
+                if x * x + y * y >= radius2 {
+                    return false;
+                }
+                if full {
+                    return true;
+                }
+
+                let corners = [ll, [ur[0], ll[1]], ur, [ll[0], ur[1]]]
+                    .map(|c| [c[0] - center[0], c[1] - center[1]]);
+                let past_right = |c: &[f32; 2]| right[0] * c[1] - right[1] * c[0] < 0.0;
+                let past_left = |c: &[f32; 2]| c[0] * left[1] - c[1] * left[0] < 0.0;
+                if convex {
+                    !(corners.iter().all(past_right) || corners.iter().all(past_left))
+                } else {
+                    // The outside of the cone is convex, the cell is skipped if all its corners are in it
+                    !corners.iter().all(|c| past_right(c) && past_left(c))
+                }
+            })
+            .flat_map(move |id| self.storage.cell(id))
+            .flat_map(|x| x.objs.iter().copied())
+            .filter(move |(_, pos_obj)| {
+                let x = pos_obj.x() - center[0];
+                let y = pos_obj.y() - center[1];
+                let d2 = x * x + y * y;
+                d2 < radius2 && (full || x * d[0] + y * d[1] >= d2.sqrt() * cos)
+            })
+    }
+
+    /// Queries for the objects closer than `radius` to `pos`, nearest first.
+    /// The cells are loaded lazily ring by ring around the cell of `pos`, and an object is only yielded
+    /// once no unloaded ring can contain a nearer one, so stopping early avoids looking at the farther cells.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::Grid;
+    ///
+    /// let mut g: Grid<(), [f32; 2]> = Grid::new(10);
+    /// let far = g.insert([25.0, 0.0], ());
+    /// let near = g.insert([3.0, 4.0], ());
+    /// let mid = g.insert([-12.0, 0.0], ());
+    ///
+    /// let sorted: Vec<_> = g.query_around_sorted([0.0, 0.0], 30.0).map(|(id, _pos)| id).collect();
+    /// assert_eq!(sorted, vec![near, mid, far]);
+    ///
+    /// let nearest = g.query_around_sorted([0.0, 0.0], 30.0).next();
+    /// assert_eq!(nearest, Some((near, [3.0, 4.0])));
+    /// ```
+    pub fn query_around_sorted(
+        &self,
+        pos: V2,
+        radius: f32,
+    ) -> impl Iterator<Item = CellObject<V2>> + '_ {
+        let ll_id = self
+            .storage
+            .cell_id(V2::from([pos.x() - radius, pos.y() - radius]));
+        let ur_id = self
+            .storage
+            .cell_id(V2::from([pos.x() + radius, pos.y() + radius]));
+        let center_id = self.storage.cell_id(pos);
+        let max_ring = (center_id.0 - ll_id.0)
+            .max(ur_id.0 - center_id.0)
+            .max(center_id.1 - ll_id.1)
+            .max(ur_id.1 - center_id.1);
+
+        SortedAround {
+            storage: &self.storage,
+            pos: [pos.x(), pos.y()],
+            radius,
+            center_id,
+            ll_id,
+            ur_id,
+            ring: 0,
+            max_ring,
+            heap: BinaryHeap::new(),
+        }
+    }
+
     pub fn query_aabb(&self, ll_: V2, ur_: V2) -> impl Iterator<Item = CellObject<V2>> + '_ {
         let ll = [ll_.x().min(ur_.x()), ll_.y().min(ur_.y())];
         let ur = [ll_.x().max(ur_.x()), ll_.y().max(ur_.y())];
@@ -337,6 +1070,155 @@ impl<O: Copy, V2: Vec2> Grid<O, V2> {
         });
     }
 
+    /// Queries for the objects inside a convex polygon (or on its border), given in any winding order.
+    /// Only the cells intersecting the polygon are visited, so a rotated view doesn't look at all the cells of its bounding box.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::Grid;
+    ///
+    /// let mut g: Grid<(), [f32; 2]> = Grid::new(10);
+    /// let a = g.insert([5.0, 1.0], ());
+    /// let b = g.insert([1.0, 3.0], ());
+    ///
+    /// // A trapezoid, wider at the top, like the view of a top-down camera
+    /// let view = [[4.0, 0.0], [6.0, 0.0], [10.0, 10.0], [0.0, 10.0]];
+    /// let inside: Vec<_> = g.query_polygon(&view).map(|(id, _pos)| id).collect();
+    ///
+    /// assert_eq!(inside, vec![a]);
+    /// ```
+    pub fn query_polygon(&self, polygon: &[V2]) -> impl Iterator<Item = CellObject<V2>> + '_ {
+        let polygon = PolygonQuery::new(polygon);
+        let rows = polygon_rows(self.storage.cell_size(), &polygon.points);
+
+        rows.into_iter()
+            .flat_map(move |(y, min_x, max_x)| {
+                (min_x..=max_x).flat_map(move |x| self.storage.cell((x, y)))
+            })
+            .flat_map(|x| x.objs.iter().copied())
+            .filter(move |(_, pos)| polygon.contains_point([pos.x(), pos.y()]))
+    }
+
+    /// Counts the objects closer than `radius` to `pos`, like `query_around(pos, radius).count()`.
+    /// Cells entirely inside the circle are counted without looking at their objects.
+    pub fn count_around(&self, pos: V2, radius: f32) -> usize {
+        let center = [pos.x(), pos.y()];
+        let radius2 = radius * radius;
+        let mut count = 0;
+
+        self.around_cells_visitor(center, radius, |cell, inside| {
+            if inside {
+                count += cell.objs.len();
+                return false;
+            }
+            count += cell
+                .objs
+                .iter()
+                .filter(|(_, p)| dist2(center, *p) < radius2)
+                .count();
+            false
+        });
+        count
+    }
+
+    /// Checks if any object is closer than `radius` to `pos`, stopping at the first one found.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::Grid;
+    ///
+    /// let mut g: Grid<(), [f32; 2]> = Grid::new(10);
+    /// g.insert([3.0, 4.0], ());
+    /// g.insert([30.0, 4.0], ());
+    ///
+    /// assert!(g.any_around([0.0, 0.0], 10.0));
+    /// assert!(!g.any_around([0.0, 0.0], 5.0));
+    /// assert_eq!(g.count_around([0.0, 0.0], 40.0), 2);
+    /// assert_eq!(g.count_aabb([0.0, 0.0], [10.0, 10.0]), 1);
+    /// ```
+    pub fn any_around(&self, pos: V2, radius: f32) -> bool {
+        let center = [pos.x(), pos.y()];
+        let radius2 = radius * radius;
+        let mut found = false;
+
+        self.around_cells_visitor(center, radius, |cell, inside| {
+            found = if inside {
+                !cell.objs.is_empty()
+            } else {
+                cell.objs.iter().any(|(_, p)| dist2(center, *p) < radius2)
+            };
+            found
+        });
+        found
+    }
+
+    /// Visits the non-empty cells intersecting the bounding box of the circle, with whether they are entirely
+    /// inside the circle. Stops when the visitor returns true.
+    fn around_cells_visitor(
+        &self,
+        center: [f32; 2],
+        radius: f32,
+        mut visitor: impl FnMut(&GridCell<V2>, bool) -> bool,
+    ) {
+        let ll_id = self
+            .storage
+            .cell_id(V2::from([center[0] - radius, center[1] - radius]));
+        let ur_id = self
+            .storage
+            .cell_id(V2::from([center[0] + radius, center[1] + radius]));
+        let radius2 = radius * radius;
+
+        for id in cell_range(ll_id, ur_id) {
+            let cell = match self.storage.cell(id) {
+                Some(x) => x,
+                None => continue,
+            };
+
+            let (ll, ur) = cell_bounds(self.storage.cell_size(), id);
+            let far_x = (center[0] - ll[0]).max(ur[0] - center[0]);
+            let far_y = (center[1] - ll[1]).max(ur[1] - center[1]);
+            if visitor(cell, far_x * far_x + far_y * far_y < radius2) {
+                return;
+            }
+        }
+    }
+
+    /// Counts the objects inside the rectangle (borders included), like `query_aabb(ll, ur).count()`.
+    /// Cells entirely inside the rectangle are counted without looking at their objects.
+    pub fn count_aabb(&self, ll_: V2, ur_: V2) -> usize {
+        let ll = [ll_.x().min(ur_.x()), ll_.y().min(ur_.y())];
+        let ur = [ll_.x().max(ur_.x()), ll_.y().max(ur_.y())];
+
+        let ll_id = self.storage.cell_id(V2::from(ll));
+        let ur_id = self.storage.cell_id(V2::from(ur));
+
+        let mut count = 0;
+        for id in cell_range(ll_id, ur_id) {
+            let cell = match self.storage.cell(id) {
+                Some(x) => x,
+                None => continue,
+            };
+
+            let (cell_ll, cell_ur) = cell_bounds(self.storage.cell_size(), id);
+            if ll[0] <= cell_ll[0]
+                && ll[1] <= cell_ll[1]
+                && cell_ur[0] <= ur[0]
+                && cell_ur[1] <= ur[1]
+            {
+                count += cell.objs.len();
+                continue;
+            }
+            count += cell
+                .objs
+                .iter()
+                .filter(|(_, p)| {
+                    (ll[0]..=ur[0]).contains(&p.x()) && (ll[1]..=ur[1]).contains(&p.y())
+                })
+                .count();
+        }
+        count
+    }
+
     /// Queries for all objects in the cells intersecting an axis-aligned rectangle defined by lower left (ll) and upper right (ur)
     /// Try to keep the rect's width/height of similar magnitudes to the cell size for better performance.
     ///
@@ -380,6 +1262,136 @@ impl<O: Copy, V2: Vec2> Grid<O, V2> {
         }
     }
 
+    /// Runs many query_around at once, writing the handles found by each query into `out`.
+    /// The queries are grouped by the cells they cover: each cell is looked up once and its objects are tested
+    /// against all the queries covering it, instead of every query hashing its own cells.
+    /// The hits of a query are not in the same order as with query_around.
+    /// This is faster than separate query_around calls when the queries are in random order, but sorting the covered
+    /// cells can cost more than the lookups it saves when the queries are already sorted spatially.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::Grid;
+    /// use flat_spatial::batch::BatchResults;
+    ///
+    /// let mut g: Grid<(), [f32; 2]> = Grid::new(10);
+    /// let a = g.insert([0.0, 0.0], ());
+    /// let b = g.insert([30.0, 0.0], ());
+    ///
+    /// let mut out = BatchResults::new();
+    /// g.query_around_batch(&[([1.0, 0.0], 5.0), ([29.0, 0.0], 5.0), ([15.0, 0.0], 1.0)], &mut out);
+    ///
+    /// assert_eq!(out.get(0), &[a]);
+    /// assert_eq!(out.get(1), &[b]);
+    /// assert!(out.get(2).is_empty());
+    /// ```
+    pub fn query_around_batch(&self, queries: &[(V2, f32)], out: &mut BatchResults<GridHandle>) {
+        let storage = &self.storage;
+
+        out.fill(
+            queries.iter().map(|&(pos, radius)| {
+                (
+                    storage.cell_id(V2::from([pos.x() - radius, pos.y() - radius])),
+                    storage.cell_id(V2::from([pos.x() + radius, pos.y() + radius])),
+                )
+            }),
+            |id| storage.cell(id),
+            |cell, _, q, hits| {
+                let (pos, radius) = queries[q];
+                let radius2 = radius * radius;
+                for (h, pos_obj) in cell.objs.iter() {
+                    let x = pos_obj.x() - pos.x();
+                    let y = pos_obj.y() - pos.y();
+                    if x * x + y * y < radius2 {
+                        hits.push(*h);
+                    }
+                }
+            },
+        );
+    }
+
+    /// Checks the internal invariants of the grid: every cell entry points to a live object
+    /// stored in this cell with the same position, every object is in its cell exactly once,
+    /// and the cells of objects with pending updates are dirty.
+    /// Useful after deserializing untrusted data, as a corrupted grid can lead to undefined behavior.
+    /// At most `MAX_ERRORS` errors are reported.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::Grid;
+    /// let mut g: Grid<(), [f32; 2]> = Grid::new(10);
+    /// let h = g.insert([5.0, 3.0], ());
+    /// g.set_position(h, [25.0, 3.0]);
+    /// assert!(g.validate().is_ok());
+    /// ```
+    pub fn validate(&self) -> Result<(), Vec<ValidationError<GridHandle>>> {
+        let storage = &self.storage;
+        let mut errors = vec![];
+        let mut seen = fnv::FnvHashSet::default();
+
+        'cells: for (&id, cell) in storage.cells.iter() {
+            for &(handle, pos) in cell.objs.iter() {
+                if errors.len() >= MAX_ERRORS {
+                    break 'cells;
+                }
+                let obj = match self.objects.get(handle) {
+                    Some(x) => x,
+                    None => {
+                        errors.push(ValidationError::DeadHandle { cell: id, handle });
+                        continue;
+                    }
+                };
+
+                if !seen.insert((id, handle)) {
+                    errors.push(ValidationError::Duplicate { cell: id, handle });
+                    continue;
+                }
+
+                if id != obj.cell_id {
+                    errors.push(ValidationError::WrongCell { cell: id, handle });
+                    continue;
+                }
+
+                if pos.x().to_bits() != obj.pos.x().to_bits()
+                    || pos.y().to_bits() != obj.pos.y().to_bits()
+                {
+                    errors.push(ValidationError::PositionMismatch { cell: id, handle });
+                }
+
+                if !matches!(obj.state, ObjectState::Unchanged) && !cell.dirty {
+                    errors.push(ValidationError::NotDirty { cell: id, handle });
+                }
+            }
+        }
+
+        for (handle, obj) in self.objects.iter() {
+            if errors.len() >= MAX_ERRORS {
+                break;
+            }
+            let valid_state = match obj.state {
+                ObjectState::NewPos(pos) => storage.cell_id(pos) == obj.cell_id,
+                ObjectState::Relocate(pos, target_id) => storage.cell_id(pos) == target_id,
+                ObjectState::Unchanged | ObjectState::Removed => true,
+            };
+            if !valid_state || storage.cell_id(obj.pos) != obj.cell_id {
+                errors.push(ValidationError::InvalidState { handle });
+            }
+
+            if !seen.contains(&(obj.cell_id, handle)) {
+                errors.push(ValidationError::MissingFromCell {
+                    cell: obj.cell_id,
+                    handle,
+                });
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
     /// Returns the number of objects currently available
     /// (removals that were not confirmed with maintain() are still counted)
     pub fn len(&self) -> usize {
@@ -392,3 +1404,247 @@ impl<O: Copy, V2: Vec2> Grid<O, V2> {
         self.objects.is_empty()
     }
 }
+
+#[cfg(feature = "rayon")]
+impl<O: Copy + Send + Sync, V2: Vec2 + Send + Sync> Grid<O, V2> {
+    /// Same as maintain_deterministic() but the dirty cells are processed in parallel using rayon.
+    /// The relocations are sorted so the result does not depend on the scheduling.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::Grid;
+    /// let mut g: Grid<(), [f32; 2]> = Grid::new(10);
+    /// let h = g.insert([5.0, 3.0], ());
+    /// g.set_position(h, [25.0, 3.0]);
+    /// g.par_maintain();
+    ///
+    /// assert_eq!(g.get(h), Some(([25.0, 3.0], &())));
+    /// ```
+    pub fn par_maintain(&mut self) {
+        use rayon::prelude::*;
+
+        let Self {
+            storage,
+            objects,
+            to_relocate,
+            history,
+            journal,
+            ..
+        } = self;
+
+        history.record_maintain(storage, objects);
+        let objects_ref = &*objects;
+        let (mut relocated, changed) = storage
+            .cells
+            .par_iter_mut()
+            .filter(|(_, cell)| cell.dirty)
+            .fold(
+                || (Vec::new(), Vec::new()),
+                |(mut relocated, mut changed), (_, cell)| {
+                    cell.maintain_shared(objects_ref, &mut relocated, &mut changed);
+                    (relocated, changed)
+                },
+            )
+            .reduce(
+                || (Vec::new(), Vec::new()),
+                |(mut relocated, mut changed), (mut r, mut c)| {
+                    relocated.append(&mut r);
+                    changed.append(&mut c);
+                    (relocated, changed)
+                },
+            );
+
+        for handle in changed {
+            let store_obj = &mut objects[handle];
+            match store_obj.state {
+                ObjectState::NewPos(pos) => {
+                    store_obj.state = ObjectState::Unchanged;
+                    store_obj.pos = pos;
+                }
+                ObjectState::Relocate(pos, target_id) => {
+                    store_obj.state = ObjectState::Unchanged;
+                    store_obj.pos = pos;
+                    store_obj.cell_id = target_id;
+                }
+                ObjectState::Removed => {
+                    history.record_removed(objects, handle);
+                    objects.remove(handle);
+                    if let Some(journal) = journal {
+                        journal.removed(handle);
+                    }
+                }
+                ObjectState::Unchanged => {}
+            }
+        }
+
+        storage.modify(|cell| cell.objs.is_empty());
+
+        to_relocate.append(&mut relocated);
+        to_relocate.sort_unstable_by_key(|obj| obj.0);
+
+        for (handle, pos) in to_relocate.drain(..) {
+            let id = storage.cell_id(pos);
+            history.record(storage, id);
+            storage.cell_mut_unchecked(id).objs.push((handle, pos));
+        }
+    }
+
+    /// Runs many independent queries concurrently using rayon.
+    /// Returns the result of `f` for each query, in the same order as `queries`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use flat_spatial::Grid;
+    /// let mut g: Grid<(), [f32; 2]> = Grid::new(10);
+    /// let a = g.insert([5.0, 3.0], ());
+    /// let b = g.insert([50.0, 3.0], ());
+    ///
+    /// let res = g.par_query_batch(&[[5.0, 3.0], [50.0, 3.0]], |g, &pos| {
+    ///     g.query_around(pos, 1.0).map(|(h, _)| h).collect::<Vec<_>>()
+    /// });
+    /// assert_eq!(res, vec![vec![a], vec![b]]);
+    /// ```
+    pub fn par_query_batch<Q: Sync, R: Send>(
+        &self,
+        queries: &[Q],
+        f: impl Fn(&Self, &Q) -> R + Sync + Send,
+    ) -> Vec<R> {
+        use rayon::prelude::*;
+
+        queries.par_iter().map(|q| f(self, q)).collect()
+    }
+}
+
+#[inline]
+fn dist2<V2: Vec2>(center: [f32; 2], p: V2) -> f32 {
+    let x = p.x() - center[0];
+    let y = p.y() - center[1];
+    x * x + y * y
+}
+
+/// Iterator of `Grid::query_around_sorted`
+struct SortedAround<'a, V2: Vec2> {
+    storage: &'a SparseStorage<GridCell<V2>>,
+    pos: [f32; 2],
+    radius: f32,
+    center_id: CellIdx,
+    ll_id: CellIdx,
+    ur_id: CellIdx,
+    /// Next ring of cells to load, at this Chebyshev distance of the center cell
+    ring: i32,
+    max_ring: i32,
+    heap: BinaryHeap<Candidate<V2>>,
+}
+
+impl<'a, V2: Vec2> SortedAround<'a, V2> {
+    /// Lower bound of the distance to the objects of a ring of cells: the distance to the border of the inner rings
+    fn ring_distance(&self, ring: i32) -> f32 {
+        if ring == 0 {
+            return 0.0;
+        }
+        let size = self.storage.cell_size() as f32;
+        let (x, y) = self.center_id;
+        let ll = [(x - ring + 1) as f32 * size, (y - ring + 1) as f32 * size];
+        let ur = [(x + ring) as f32 * size, (y + ring) as f32 * size];
+
+        (self.pos[0] - ll[0])
+            .min(ur[0] - self.pos[0])
+            .min(self.pos[1] - ll[1])
+            .min(ur[1] - self.pos[1])
+            .max(0.0)
+    }
+
+    fn load_cell(&mut self, id: CellIdx) {
+        if id.0 < self.ll_id.0 || id.0 > self.ur_id.0 || id.1 < self.ll_id.1 || id.1 > self.ur_id.1
+        {
+            return;
+        }
+        let cell = match self.storage.cell(id) {
+            Some(x) => x,
+            None => return,
+        };
+
+        let radius2 = self.radius * self.radius;
+        for &(h, pos) in cell.objs.iter() {
+            let x = pos.x() - self.pos[0];
+            let y = pos.y() - self.pos[1];
+            let dist2 = x * x + y * y;
+            if dist2 < radius2 {
+                self.heap.push(Candidate {
+                    dist2,
+                    obj: (h, pos),
+                });
+            }
+        }
+    }
+
+    fn load_ring(&mut self) {
+        let k = self.ring;
+        let (cx, cy) = self.center_id;
+        self.ring += 1;
+
+        if k == 0 {
+            self.load_cell((cx, cy));
+            return;
+        }
+        for x in cx - k..=cx + k {
+            self.load_cell((x, cy - k));
+            self.load_cell((x, cy + k));
+        }
+        for y in cy - k + 1..cy + k {
+            self.load_cell((cx - k, y));
+            self.load_cell((cx + k, y));
+        }
+    }
+}
+
+impl<'a, V2: Vec2> Iterator for SortedAround<'a, V2> {
+    type Item = CellObject<V2>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let exhausted =
+                self.ring > self.max_ring || self.ring_distance(self.ring) >= self.radius;
+            if exhausted {
+                return self.heap.pop().map(|c| c.obj);
+            }
+
+            if let Some(nearest) = self.heap.peek() {
+                let bound = self.ring_distance(self.ring);
+                if nearest.dist2 <= bound * bound {
+                    return self.heap.pop().map(|c| c.obj);
+                }
+            }
+            self.load_ring();
+        }
+    }
+}
+
+/// An object found by `SortedAround`, ordered so that the nearest is at the top of the heap
+struct Candidate<V2: Vec2> {
+    dist2: f32,
+    obj: CellObject<V2>,
+}
+
+impl<V2: Vec2> PartialEq for Candidate<V2> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<V2: Vec2> Eq for Candidate<V2> {}
+
+impl<V2: Vec2> PartialOrd for Candidate<V2> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<V2: Vec2> Ord for Candidate<V2> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .dist2
+            .partial_cmp(&self.dist2)
+            .unwrap_or(Ordering::Equal)
+    }
+}
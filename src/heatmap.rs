@@ -0,0 +1,68 @@
+use crate::storage::CellIdx;
+use std::io::Write;
+use std::path::Path;
+
+/// `Heatmap` is a raster of the number of objects per cell over a rectangle of cells,
+/// obtained with `SparseStorage::rasterize`, `Grid::heatmap` or `AABBGrid::heatmap`.
+///
+/// `counts` is stored row by row, starting from the lowest cell y, so the count of cell (x, y) is at
+/// `(y - origin.1) * width + (x - origin.0)`.
+/// The images are exported in the same order: the first row of the image is the lowest cell y.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Heatmap {
+    /// Id of the lower left cell of the raster
+    pub origin: CellIdx,
+    pub width: usize,
+    pub height: usize,
+    pub counts: Vec<u32>,
+}
+
+impl Heatmap {
+    /// Returns the count of the given cell, or None if it is outside of the raster.
+    pub fn get(&self, (x, y): CellIdx) -> Option<u32> {
+        let x = usize::try_from(x.checked_sub(self.origin.0)?).ok()?;
+        let y = usize::try_from(y.checked_sub(self.origin.1)?).ok()?;
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some(self.counts[y * self.width + x])
+    }
+
+    /// Returns the highest count of the raster
+    pub fn max(&self) -> u32 {
+        self.counts.iter().copied().max().unwrap_or(0)
+    }
+
+    /// Encodes the heatmap as a binary grayscale PGM image, where the counts are scaled so that the highest one is white.
+    pub fn to_pgm(&self) -> Vec<u8> {
+        let mut out = format!("P5\n{} {}\n255\n", self.width, self.height).into_bytes();
+        let max = self.max().max(1) as u64;
+        out.extend(self.counts.iter().map(|&c| (c as u64 * 255 / max) as u8));
+        out
+    }
+
+    /// Encodes the heatmap as a binary PPM image, using a black-red-yellow-white color ramp
+    /// where the highest count is white.
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut out = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        let max = self.max().max(1) as u64;
+        for &c in self.counts.iter() {
+            let v = (c as u64 * 765 / max) as u32;
+            let r = v.min(255);
+            let g = v.saturating_sub(255).min(255);
+            let b = v.saturating_sub(510).min(255);
+            out.extend_from_slice(&[r as u8, g as u8, b as u8]);
+        }
+        out
+    }
+
+    /// Writes the heatmap to a grayscale PGM file, see `to_pgm`.
+    pub fn write_pgm(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::File::create(path)?.write_all(&self.to_pgm())
+    }
+
+    /// Writes the heatmap to a color PPM file, see `to_ppm`.
+    pub fn write_ppm(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::File::create(path)?.write_all(&self.to_ppm())
+    }
+}
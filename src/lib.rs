@@ -4,16 +4,35 @@
 //!
 //! `Grid` partitions the space using cells of user defined width.
 //! `AABBGrid` partitions the space using cells too, but stores Axis-Aligned Bounding Boxes.
+//! `CircleGrid` is built on top of `AABBGrid` and stores circles with exact overlap tests.
+//! The `shape` module has circles, capsules, oriented boxes and convex polygons to store in an `AABBGrid`.
+//! `FrozenGrid` is an immutable snapshot of a `Grid` with a more compact layout, for static data.
 //!
 //! Check `Grid` and `AABBGrid` docs for more information.
 //!
 
 pub mod aabbgrid;
+#[cfg(feature = "rkyv")]
+pub mod archive;
+pub mod batch;
 pub mod cell;
+pub mod circlegrid;
+#[cfg(feature = "serde")]
+pub mod compact;
+pub mod delta;
+pub mod frozen;
 pub mod grid;
+pub mod heatmap;
+pub mod shape;
+pub mod snapshot;
+pub mod stats;
 pub mod storage;
+pub mod svg;
+pub mod validate;
 
 pub use aabbgrid::AABBGrid;
+pub use circlegrid::CircleGrid;
+pub use frozen::FrozenGrid;
 pub use grid::Grid;
 
 pub trait Vec2: From<[f32; 2]> + Copy {
@@ -0,0 +1,659 @@
+//! Convex shapes that can be stored in an `AABBGrid` with an exact narrow phase.
+//!
+//! Every shape implements `Shape`, which gives its bounding box and an exact intersection test against any other
+//! shape (using the GJK algorithm on their support functions), and `AABB`, so that `AABBGrid` uses the bounding box
+//! to pick the cells and the exact test in `query`.
+//! `AnyShape` allows mixing different kinds of shapes in the same grid.
+//!
+//! ```rust
+//! use flat_spatial::AABBGrid;
+//! use flat_spatial::shape::{AnyShape, Capsule, Circle, ConvexPolygon, Obb};
+//!
+//! let mut g: AABBGrid<(), AnyShape<[f32; 2]>> = AABBGrid::new(10);
+//! let tri = g.insert(ConvexPolygon::new(&[[0.0, 0.0], [10.0, 0.0], [0.0, 10.0]]).into(), ());
+//! let caps = g.insert(Capsule::new([20.0, 0.0], [20.0, 20.0], 1.0).into(), ());
+//! let _obb = g.insert(Obb::new([40.0, 40.0], [5.0, 1.0], 0.7).into(), ());
+//!
+//! // The bounding box of the triangle contains the point (8, 8) but the triangle does not
+//! let hits: Vec<_> = g.query(Circle::new([8.0, 8.0], 1.0).into()).map(|(h, _, _)| h).collect();
+//! assert!(hits.is_empty());
+//!
+//! let hits: Vec<_> = g.query(Circle::new([4.0, 4.0], 1.0).into()).map(|(h, _, _)| h).collect();
+//! assert_eq!(hits, vec![tri]);
+//!
+//! let hits: Vec<_> = g.query_shape(Circle::new([22.0, 10.0], 1.5)).map(|(h, _, _)| h).collect();
+//! assert_eq!(hits, vec![caps]);
+//! ```
+
+use crate::{Vec2, AABB};
+
+/// Maximum number of vertices of a `ConvexPolygon`
+pub const MAX_POLYGON_VERTICES: usize = 8;
+
+/// A convex shape with an exact intersection test.
+pub trait Shape: Copy {
+    type V2: Vec2;
+
+    /// The smallest axis-aligned box containing the shape
+    fn bounding_aabb(&self) -> BoundingBox<Self::V2>;
+
+    /// The point of the shape that is the farthest in the given direction (which is not normalized)
+    fn support(&self, dir: [f32; 2]) -> [f32; 2];
+
+    /// Checks if the two shapes intersect (touching counts as intersecting)
+    fn intersects_shape<S: Shape>(&self, other: &S) -> bool {
+        gjk_intersects(self, other)
+    }
+}
+
+/// An axis-aligned box given by its lower left and upper right corners.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BoundingBox<V2: Vec2> {
+    pub ll: V2,
+    pub ur: V2,
+}
+
+impl<V2: Vec2> BoundingBox<V2> {
+    pub fn new(ll: V2, ur: V2) -> Self {
+        Self { ll, ur }
+    }
+}
+
+impl<V2: Vec2> AABB for BoundingBox<V2> {
+    type V2 = V2;
+
+    #[inline]
+    fn ll(&self) -> V2 {
+        self.ll
+    }
+
+    #[inline]
+    fn ur(&self) -> V2 {
+        self.ur
+    }
+}
+
+impl<V2: Vec2> Shape for BoundingBox<V2> {
+    type V2 = V2;
+
+    fn bounding_aabb(&self) -> BoundingBox<V2> {
+        *self
+    }
+
+    #[inline]
+    fn support(&self, dir: [f32; 2]) -> [f32; 2] {
+        [
+            if dir[0] >= 0.0 {
+                self.ur.x()
+            } else {
+                self.ll.x()
+            },
+            if dir[1] >= 0.0 {
+                self.ur.y()
+            } else {
+                self.ll.y()
+            },
+        ]
+    }
+
+    fn intersects_shape<S: Shape>(&self, other: &S) -> bool {
+        let b = other.bounding_aabb();
+        let bbox = BoundingBox::<V2>::new([b.ll.x(), b.ll.y()].into(), [b.ur.x(), b.ur.y()].into());
+        // Boxes are common as queries, skip GJK when the other bounding box is enough to decide
+        if !self.intersects(&bbox) {
+            return false;
+        }
+        gjk_intersects(self, other)
+    }
+}
+
+/// A circle defined by its center and radius.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Circle<V2: Vec2> {
+    pub center: V2,
+    pub radius: f32,
+}
+
+impl<V2: Vec2> Circle<V2> {
+    pub fn new(center: V2, radius: f32) -> Self {
+        Self { center, radius }
+    }
+
+    /// Checks if the point is inside the circle (or on its border)
+    #[inline]
+    pub fn contains(&self, p: V2) -> bool {
+        let x = p.x() - self.center.x();
+        let y = p.y() - self.center.y();
+        x * x + y * y <= self.radius * self.radius
+    }
+}
+
+impl<V2: Vec2> Shape for Circle<V2> {
+    type V2 = V2;
+
+    fn bounding_aabb(&self) -> BoundingBox<V2> {
+        BoundingBox::new(self.ll(), self.ur())
+    }
+
+    #[inline]
+    fn support(&self, dir: [f32; 2]) -> [f32; 2] {
+        let c = [self.center.x(), self.center.y()];
+        add(c, scale(normalize(dir), self.radius))
+    }
+}
+
+/// The circle-circle test doesn't need GJK
+impl<V2: Vec2> AABB for Circle<V2> {
+    type V2 = V2;
+
+    #[inline]
+    fn ll(&self) -> V2 {
+        [self.center.x() - self.radius, self.center.y() - self.radius].into()
+    }
+
+    #[inline]
+    fn ur(&self) -> V2 {
+        [self.center.x() + self.radius, self.center.y() + self.radius].into()
+    }
+
+    #[inline]
+    fn intersects(&self, b: &Self) -> bool {
+        let x = b.center.x() - self.center.x();
+        let y = b.center.y() - self.center.y();
+        let r = self.radius + b.radius;
+        x * x + y * y <= r * r
+    }
+}
+
+/// A capsule: all the points within `radius` of the segment from `a` to `b`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Capsule<V2: Vec2> {
+    pub a: V2,
+    pub b: V2,
+    pub radius: f32,
+}
+
+impl<V2: Vec2> Capsule<V2> {
+    pub fn new(a: V2, b: V2, radius: f32) -> Self {
+        Self { a, b, radius }
+    }
+}
+
+impl<V2: Vec2> Shape for Capsule<V2> {
+    type V2 = V2;
+
+    fn bounding_aabb(&self) -> BoundingBox<V2> {
+        let r = self.radius;
+        BoundingBox::new(
+            [
+                self.a.x().min(self.b.x()) - r,
+                self.a.y().min(self.b.y()) - r,
+            ]
+            .into(),
+            [
+                self.a.x().max(self.b.x()) + r,
+                self.a.y().max(self.b.y()) + r,
+            ]
+            .into(),
+        )
+    }
+
+    #[inline]
+    fn support(&self, dir: [f32; 2]) -> [f32; 2] {
+        let a = [self.a.x(), self.a.y()];
+        let b = [self.b.x(), self.b.y()];
+        let p = if dot(a, dir) >= dot(b, dir) { a } else { b };
+        add(p, scale(normalize(dir), self.radius))
+    }
+}
+
+/// An oriented box given by its center, its half width and height, and its local x axis (a unit vector).
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Obb<V2: Vec2> {
+    pub center: V2,
+    pub half_extents: V2,
+    pub axis: V2,
+}
+
+impl<V2: Vec2> Obb<V2> {
+    /// Creates a box rotated by `angle` radians counter-clockwise around its center.
+    pub fn new(center: V2, half_extents: V2, angle: f32) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        Self {
+            center,
+            half_extents,
+            axis: [cos, sin].into(),
+        }
+    }
+
+    /// The four corners, counter-clockwise
+    pub fn corners(&self) -> [[f32; 2]; 4] {
+        let c = [self.center.x(), self.center.y()];
+        let u = scale([self.axis.x(), self.axis.y()], self.half_extents.x());
+        let v = scale([-self.axis.y(), self.axis.x()], self.half_extents.y());
+        [
+            sub(sub(c, u), v),
+            sub(add(c, u), v),
+            add(add(c, u), v),
+            add(sub(c, u), v),
+        ]
+    }
+}
+
+impl<V2: Vec2> Shape for Obb<V2> {
+    type V2 = V2;
+
+    fn bounding_aabb(&self) -> BoundingBox<V2> {
+        let (ax, ay) = (self.axis.x().abs(), self.axis.y().abs());
+        let (hx, hy) = (self.half_extents.x(), self.half_extents.y());
+        let w = ax * hx + ay * hy;
+        let h = ay * hx + ax * hy;
+        let (cx, cy) = (self.center.x(), self.center.y());
+        BoundingBox::new([cx - w, cy - h].into(), [cx + w, cy + h].into())
+    }
+
+    #[inline]
+    fn support(&self, dir: [f32; 2]) -> [f32; 2] {
+        let u = [self.axis.x(), self.axis.y()];
+        let v = [-u[1], u[0]];
+        let su = if dot(u, dir) >= 0.0 { 1.0 } else { -1.0 };
+        let sv = if dot(v, dir) >= 0.0 { 1.0 } else { -1.0 };
+        let c = [self.center.x(), self.center.y()];
+        add(
+            c,
+            add(
+                scale(u, su * self.half_extents.x()),
+                scale(v, sv * self.half_extents.y()),
+            ),
+        )
+    }
+}
+
+/// A convex polygon with up to `MAX_POLYGON_VERTICES` vertices.
+/// The vertices can be given in any winding order, but the polygon must be convex.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConvexPolygon<V2: Vec2> {
+    /// Unused vertices repeat the first one, which doesn't change the shape
+    points: [V2; MAX_POLYGON_VERTICES],
+    len: u8,
+}
+
+impl<V2: Vec2> ConvexPolygon<V2> {
+    /// Creates a polygon from its vertices.
+    /// Panics if there are no vertices or more than `MAX_POLYGON_VERTICES`.
+    pub fn new(points: &[V2]) -> Self {
+        assert!(
+            !points.is_empty() && points.len() <= MAX_POLYGON_VERTICES,
+            "A convex polygon must have between 1 and {} vertices, got {}",
+            MAX_POLYGON_VERTICES,
+            points.len()
+        );
+        let mut padded = [points[0]; MAX_POLYGON_VERTICES];
+        padded[..points.len()].copy_from_slice(points);
+        Self {
+            points: padded,
+            len: points.len() as u8,
+        }
+    }
+
+    /// The vertices, as given to `new`
+    pub fn points(&self) -> &[V2] {
+        &self.points[..self.len as usize]
+    }
+}
+
+impl<V2: Vec2> Shape for ConvexPolygon<V2> {
+    type V2 = V2;
+
+    fn bounding_aabb(&self) -> BoundingBox<V2> {
+        let mut ll = [f32::INFINITY; 2];
+        let mut ur = [f32::NEG_INFINITY; 2];
+        for p in self.points() {
+            ll = [ll[0].min(p.x()), ll[1].min(p.y())];
+            ur = [ur[0].max(p.x()), ur[1].max(p.y())];
+        }
+        BoundingBox::new(ll.into(), ur.into())
+    }
+
+    #[inline]
+    fn support(&self, dir: [f32; 2]) -> [f32; 2] {
+        let mut best = [self.points[0].x(), self.points[0].y()];
+        let mut best_dot = dot(best, dir);
+        for p in &self.points[1..] {
+            let p = [p.x(), p.y()];
+            let d = dot(p, dir);
+            if d > best_dot {
+                best = p;
+                best_dot = d;
+            }
+        }
+        best
+    }
+}
+
+/// Any of the shapes of this module, to store different kinds of shapes in the same grid.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AnyShape<V2: Vec2> {
+    Box(BoundingBox<V2>),
+    Circle(Circle<V2>),
+    Capsule(Capsule<V2>),
+    Obb(Obb<V2>),
+    Polygon(ConvexPolygon<V2>),
+}
+
+impl<V2: Vec2> Shape for AnyShape<V2> {
+    type V2 = V2;
+
+    fn bounding_aabb(&self) -> BoundingBox<V2> {
+        match self {
+            AnyShape::Box(x) => x.bounding_aabb(),
+            AnyShape::Circle(x) => x.bounding_aabb(),
+            AnyShape::Capsule(x) => x.bounding_aabb(),
+            AnyShape::Obb(x) => x.bounding_aabb(),
+            AnyShape::Polygon(x) => x.bounding_aabb(),
+        }
+    }
+
+    #[inline]
+    fn support(&self, dir: [f32; 2]) -> [f32; 2] {
+        match self {
+            AnyShape::Box(x) => x.support(dir),
+            AnyShape::Circle(x) => x.support(dir),
+            AnyShape::Capsule(x) => x.support(dir),
+            AnyShape::Obb(x) => x.support(dir),
+            AnyShape::Polygon(x) => x.support(dir),
+        }
+    }
+}
+
+macro_rules! shape_conversions {
+    ($($variant:ident($t:ident)),*) => {
+        $(
+            impl<V2: Vec2> From<$t<V2>> for AnyShape<V2> {
+                fn from(x: $t<V2>) -> Self {
+                    AnyShape::$variant(x)
+                }
+            }
+        )*
+    };
+}
+
+shape_conversions!(
+    Box(BoundingBox),
+    Circle(Circle),
+    Capsule(Capsule),
+    Obb(Obb),
+    Polygon(ConvexPolygon)
+);
+
+macro_rules! shape_aabb {
+    ($($t:ident),*) => {
+        $(
+            /// The bounding box is used for the broad phase, and `Shape::intersects_shape` for the narrow phase
+            impl<V2: Vec2> AABB for $t<V2> {
+                type V2 = V2;
+
+                #[inline]
+                fn ll(&self) -> V2 {
+                    self.bounding_aabb().ll
+                }
+
+                #[inline]
+                fn ur(&self) -> V2 {
+                    self.bounding_aabb().ur
+                }
+
+                #[inline]
+                fn intersects(&self, b: &Self) -> bool {
+                    self.intersects_shape(b)
+                }
+            }
+        )*
+    };
+}
+
+shape_aabb!(Capsule, Obb, ConvexPolygon, AnyShape);
+
+/// A convex polygon of any size used as a query, with exact point and box tests.
+pub(crate) struct PolygonQuery {
+    pub(crate) points: Vec<[f32; 2]>,
+    /// Sign of the area: 1 for counter-clockwise, -1 for clockwise and 0 for a degenerate polygon
+    winding: f32,
+    ll: [f32; 2],
+    ur: [f32; 2],
+}
+
+impl PolygonQuery {
+    pub(crate) fn new<V2: Vec2>(polygon: &[V2]) -> Self {
+        let points: Vec<[f32; 2]> = polygon.iter().map(|p| [p.x(), p.y()]).collect();
+
+        let mut area = 0.0;
+        let mut ll = [f32::INFINITY; 2];
+        let mut ur = [f32::NEG_INFINITY; 2];
+        for (i, &p) in points.iter().enumerate() {
+            let q = points[(i + 1) % points.len()];
+            area += p[0] * q[1] - q[0] * p[1];
+            ll = [ll[0].min(p[0]), ll[1].min(p[1])];
+            ur = [ur[0].max(p[0]), ur[1].max(p[1])];
+        }
+
+        let winding = if area > 0.0 {
+            1.0
+        } else if area < 0.0 {
+            -1.0
+        } else {
+            0.0
+        };
+
+        Self {
+            points,
+            winding,
+            ll,
+            ur,
+        }
+    }
+
+    fn edges(&self) -> impl Iterator<Item = ([f32; 2], [f32; 2])> + '_ {
+        let n = self.points.len();
+        (0..n).map(move |i| (self.points[i], self.points[(i + 1) % n]))
+    }
+
+    /// Checks if the point is inside the polygon (or on its border)
+    pub(crate) fn contains_point(&self, p: [f32; 2]) -> bool {
+        if !(self.ll[0] <= p[0] && p[0] <= self.ur[0] && self.ll[1] <= p[1] && p[1] <= self.ur[1]) {
+            return false;
+        }
+        self.edges().all(|(a, b)| {
+            let cross = perp_dot(sub(b, a), sub(p, a));
+            if self.winding == 0.0 {
+                cross == 0.0
+            } else {
+                cross * self.winding >= 0.0
+            }
+        })
+    }
+
+    /// Checks if the axis-aligned box intersects the polygon (touching counts as intersecting), using the
+    /// separating axis theorem
+    pub(crate) fn intersects_box(&self, ll: [f32; 2], ur: [f32; 2]) -> bool {
+        if self.points.is_empty()
+            || self.ur[0] < ll[0]
+            || ur[0] < self.ll[0]
+            || self.ur[1] < ll[1]
+            || ur[1] < self.ll[1]
+        {
+            return false;
+        }
+
+        let corners = [ll, [ur[0], ll[1]], ur, [ll[0], ur[1]]];
+        self.edges().all(|(a, b)| {
+            let normal = [a[1] - b[1], b[0] - a[0]];
+            let (min_poly, max_poly) = min_max(self.points.iter().map(|&p| dot(p, normal)));
+            let (min_box, max_box) = min_max(corners.iter().map(|&p| dot(p, normal)));
+            min_poly <= max_box && min_box <= max_poly
+        })
+    }
+}
+
+fn min_max(values: impl Iterator<Item = f32>) -> (f32, f32) {
+    values.fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), v| {
+        (min.min(v), max.max(v))
+    })
+}
+
+#[inline]
+fn perp_dot(a: [f32; 2], b: [f32; 2]) -> f32 {
+    a[0] * b[1] - a[1] * b[0]
+}
+
+#[inline]
+fn add(a: [f32; 2], b: [f32; 2]) -> [f32; 2] {
+    [a[0] + b[0], a[1] + b[1]]
+}
+
+#[inline]
+fn sub(a: [f32; 2], b: [f32; 2]) -> [f32; 2] {
+    [a[0] - b[0], a[1] - b[1]]
+}
+
+#[inline]
+fn scale(a: [f32; 2], s: f32) -> [f32; 2] {
+    [a[0] * s, a[1] * s]
+}
+
+#[inline]
+fn dot(a: [f32; 2], b: [f32; 2]) -> f32 {
+    a[0] * b[0] + a[1] * b[1]
+}
+
+#[inline]
+fn normalize(a: [f32; 2]) -> [f32; 2] {
+    let l = dot(a, a).sqrt();
+    if l > 0.0 {
+        scale(a, 1.0 / l)
+    } else {
+        [0.0, 0.0]
+    }
+}
+
+/// Perpendicular of `a` on the side of `towards`
+#[inline]
+fn perp_towards(a: [f32; 2], towards: [f32; 2]) -> [f32; 2] {
+    let p = [-a[1], a[0]];
+    if dot(p, towards) >= 0.0 {
+        p
+    } else {
+        [a[1], -a[0]]
+    }
+}
+
+/// GJK intersection test: the shapes intersect if their Minkowski difference contains the origin.
+/// The simplex is grown towards the origin until it contains it, or until a support point shows
+/// that the origin is out of reach.
+fn gjk_intersects<A: Shape, B: Shape>(a: &A, b: &B) -> bool {
+    const MAX_ITERATIONS: usize = 32;
+
+    let support = |d: [f32; 2]| sub(a.support(d), b.support([-d[0], -d[1]]));
+
+    let center = |bbox: [[f32; 2]; 2]| scale(add(bbox[0], bbox[1]), 0.5);
+    let (ba, bb) = (a.bounding_aabb(), b.bounding_aabb());
+    let mut d = sub(
+        center([[ba.ll.x(), ba.ll.y()], [ba.ur.x(), ba.ur.y()]]),
+        center([[bb.ll.x(), bb.ll.y()], [bb.ur.x(), bb.ur.y()]]),
+    );
+    if d == [0.0, 0.0] {
+        d = [1.0, 0.0];
+    }
+
+    // simplex[n - 1] is always the most recent point
+    let mut simplex = [support(d); 3];
+    let mut n = 1;
+    d = scale(simplex[0], -1.0);
+
+    for _ in 0..MAX_ITERATIONS {
+        if d == [0.0, 0.0] {
+            // The origin is on the simplex
+            return true;
+        }
+
+        let p = support(d);
+        if dot(p, d) < 0.0 {
+            // The farthest point towards the origin doesn't reach it
+            return false;
+        }
+        simplex[n] = p;
+        n += 1;
+
+        let a = simplex[n - 1];
+        let ao = scale(a, -1.0);
+        if n == 2 {
+            let ab = sub(simplex[0], a);
+            let p = [-ab[1], ab[0]];
+            if dot(p, ao) == 0.0 {
+                // The origin is on the segment
+                return true;
+            }
+            d = perp_towards(ab, ao);
+        } else {
+            let (b, c) = (simplex[1], simplex[0]);
+            let ab = sub(b, a);
+            let ac = sub(c, a);
+            let ab_perp = perp_towards(ab, scale(ac, -1.0));
+            let ac_perp = perp_towards(ac, scale(ab, -1.0));
+
+            if dot(ab_perp, ao) > 0.0 {
+                simplex = [b, a, a];
+                n = 2;
+                d = ab_perp;
+            } else if dot(ac_perp, ao) > 0.0 {
+                simplex = [c, a, a];
+                n = 2;
+                d = ac_perp;
+            } else {
+                return true;
+            }
+        }
+    }
+
+    // Only reached when the shapes are within floating point precision of touching
+    // Touching within the precision of the coordinates counts as intersecting
+    let tolerance = 1e-5 * extent(&ba).max(extent(&bb)).max(1.0);
+    distance_check(support, simplex[n - 1], tolerance)
+}
+
+/// Largest absolute coordinate of the box
+fn extent<V2: Vec2>(b: &BoundingBox<V2>) -> f32 {
+    [b.ll.x(), b.ll.y(), b.ur.x(), b.ur.y()]
+        .iter()
+        .fold(0.0, |acc, x| acc.max(x.abs()))
+}
+
+/// Fallback of `gjk_intersects` when it doesn't converge: moves a point `v` of the Minkowski difference towards
+/// the origin. `|v|` is an upper bound of the distance between the shapes, and `v` is a separating axis
+/// as soon as no point of the difference is beyond it.
+fn distance_check(support: impl Fn([f32; 2]) -> [f32; 2], mut v: [f32; 2], tolerance: f32) -> bool {
+    const MAX_ITERATIONS: usize = 64;
+    let tolerance2 = tolerance * tolerance;
+
+    for _ in 0..MAX_ITERATIONS {
+        if dot(v, v) <= tolerance2 {
+            return true;
+        }
+        let w = support(scale(v, -1.0));
+        if dot(w, v) > 0.0 {
+            return false;
+        }
+        // Closest point to the origin on the segment from v to w
+        let vw = sub(w, v);
+        let t = (-dot(v, vw) / dot(vw, vw)).clamp(0.0, 1.0);
+        v = add(v, scale(vw, t));
+    }
+
+    dot(v, v) <= tolerance2
+}
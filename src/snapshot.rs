@@ -0,0 +1,293 @@
+use crate::cell::GridCell;
+use crate::grid::{GridHandle, GridObjects, ObjectState, StoreObject};
+use crate::storage::{CellIdx, SparseStorage};
+use crate::Vec2;
+
+/// A restore point of a `Grid`, returned by `Grid::snapshot` and used by `Grid::restore`.
+/// It is only a token, the actual data is kept by the grid that created it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GridSnapshot(u64);
+
+/// A change of the handle allocation of a grid, replayed in order to rebuild the allocation state.
+enum SlotOp<O, V2: Vec2> {
+    Inserted(GridHandle, StoreObject<O, V2>),
+    Removed(GridHandle),
+    Cleared,
+}
+
+/// Applies the same insertions and removals, which gives the same handles as slot maps are deterministic.
+fn replay<O: Copy, V2: Vec2>(objects: &mut GridObjects<O, V2>, ops: &[SlotOp<O, V2>]) {
+    for op in ops {
+        match *op {
+            SlotOp::Inserted(handle, obj) => {
+                let inserted = objects.insert(obj);
+                debug_assert_eq!(inserted, handle);
+            }
+            SlotOp::Removed(handle) => {
+                objects.remove(handle);
+            }
+            SlotOp::Cleared => *objects = Default::default(),
+        }
+    }
+}
+
+/// The changes made to a grid since a snapshot was taken and until the next one.
+struct Frame<O, V2: Vec2> {
+    id: u64,
+    /// Objects before their first modification in this frame, None if the object was inserted in this frame
+    objects: fnv::FnvHashMap<GridHandle, Option<StoreObject<O, V2>>>,
+    /// Insertions and removals made in this frame, in order
+    ops: Vec<SlotOp<O, V2>>,
+    /// Content of the cells before their first modification in this frame, None if the cell did not exist
+    cells: fnv::FnvHashMap<CellIdx, Option<GridCell<V2>>>,
+    /// Whole storage before it was replaced by clear() or rebuild(), the cells modified afterwards don't need to be saved
+    storage: Option<SparseStorage<GridCell<V2>>>,
+}
+
+impl<O, V2: Vec2> Frame<O, V2> {
+    /// Brings the cells back to the state they had at the start of this frame.
+    fn undo_cells(&mut self, storage: &mut SparseStorage<GridCell<V2>>) {
+        if let Some(old) = self.storage.take() {
+            *storage = old;
+        }
+        for (id, cell) in self.cells.drain() {
+            match cell {
+                Some(cell) => storage.cells.insert(id, cell),
+                None => storage.cells.remove(&id),
+            };
+        }
+    }
+
+    fn clear(&mut self) {
+        self.objects.clear();
+        self.ops.clear();
+        self.cells.clear();
+        self.storage = None;
+    }
+}
+
+/// Journal of the cell and object modifications made since the live snapshots of a grid.
+/// Nothing is recorded while there are no snapshots.
+///
+/// Slot maps cannot insert at a given handle and their versions only increase, so undoing an insertion or
+/// a removal would not give back the same handles. Instead, the handle allocation state of the oldest snapshot
+/// is kept in `base`, and restoring replays the insertions and removals made until the restored snapshot on it.
+pub(crate) struct History<O, V2: Vec2> {
+    next_id: u64,
+    /// Live snapshots, oldest first
+    frames: Vec<Frame<O, V2>>,
+    /// Forgotten frames, kept to reuse their allocations
+    pool: Vec<Frame<O, V2>>,
+    /// The objects at the oldest live snapshot, only their handles are up to date
+    base: Option<GridObjects<O, V2>>,
+}
+
+impl<O, V2: Vec2> Default for History<O, V2> {
+    fn default() -> Self {
+        Self {
+            next_id: 0,
+            frames: Vec::new(),
+            pool: Vec::new(),
+            base: None,
+        }
+    }
+}
+
+impl<O: Copy, V2: Vec2> History<O, V2> {
+    pub(crate) fn is_recording(&self) -> bool {
+        !self.frames.is_empty()
+    }
+
+    /// Saves the content of a cell that is about to be modified, if it was not already saved since the last snapshot.
+    pub(crate) fn record(&mut self, storage: &SparseStorage<GridCell<V2>>, id: CellIdx) {
+        if let Some(frame) = self.frames.last_mut() {
+            if frame.storage.is_none() {
+                frame
+                    .cells
+                    .entry(id)
+                    .or_insert_with(|| storage.cell(id).cloned());
+            }
+        }
+    }
+
+    /// Saves the cells that maintain() is about to modify, and the objects of these cells that have a pending update.
+    /// The removals themselves are recorded by `record_removed` when they happen, as their order matters.
+    pub(crate) fn record_maintain(
+        &mut self,
+        storage: &SparseStorage<GridCell<V2>>,
+        objects: &GridObjects<O, V2>,
+    ) {
+        if !self.is_recording() {
+            return;
+        }
+        // Empty cells are removed even if they are not dirty
+        for (&id, cell) in storage.cells.iter() {
+            if !cell.dirty && !cell.objs.is_empty() {
+                continue;
+            }
+            self.record(storage, id);
+            if !cell.dirty {
+                continue;
+            }
+            for &(handle, _) in cell.objs.iter() {
+                if !matches!(objects[handle].state, ObjectState::Unchanged) {
+                    self.record_object(objects, handle);
+                }
+            }
+        }
+    }
+
+    /// Saves an object that is about to be modified, if it was not already saved since the last snapshot.
+    pub(crate) fn record_object(&mut self, objects: &GridObjects<O, V2>, handle: GridHandle) {
+        if let Some(frame) = self.frames.last_mut() {
+            if let Some(obj) = objects.get(handle) {
+                frame.objects.entry(handle).or_insert(Some(*obj));
+            }
+        }
+    }
+
+    /// Saves all the objects, before they are all modified or removed.
+    pub(crate) fn record_all_objects(&mut self, objects: &GridObjects<O, V2>) {
+        if let Some(frame) = self.frames.last_mut() {
+            for (handle, obj) in objects.iter() {
+                frame.objects.entry(handle).or_insert(Some(*obj));
+            }
+        }
+    }
+
+    /// Records an object that was just inserted.
+    pub(crate) fn record_inserted(&mut self, objects: &GridObjects<O, V2>, handle: GridHandle) {
+        if let Some(frame) = self.frames.last_mut() {
+            frame.objects.entry(handle).or_insert(None);
+            frame.ops.push(SlotOp::Inserted(handle, objects[handle]));
+        }
+    }
+
+    /// Saves an object that is about to be removed from the objects.
+    pub(crate) fn record_removed(&mut self, objects: &GridObjects<O, V2>, handle: GridHandle) {
+        if let Some(frame) = self.frames.last_mut() {
+            if let Some(obj) = objects.get(handle) {
+                frame.objects.entry(handle).or_insert(Some(*obj));
+                frame.ops.push(SlotOp::Removed(handle));
+            }
+        }
+    }
+
+    /// Saves all the objects before they are replaced by an empty slot map.
+    pub(crate) fn record_cleared(&mut self, objects: &GridObjects<O, V2>) {
+        self.record_all_objects(objects);
+        if let Some(frame) = self.frames.last_mut() {
+            frame.ops.push(SlotOp::Cleared);
+        }
+    }
+
+    /// Saves a storage that is being replaced.
+    pub(crate) fn record_storage(&mut self, storage: SparseStorage<GridCell<V2>>) {
+        if let Some(frame) = self.frames.last_mut() {
+            if frame.storage.is_none() {
+                frame.storage = Some(storage);
+            }
+        }
+    }
+
+    /// The first snapshot copies the objects to keep their handle allocation state.
+    pub(crate) fn snapshot(&mut self, objects: &GridObjects<O, V2>) -> GridSnapshot {
+        let mut frame = self.pool.pop().unwrap_or_else(|| Frame {
+            id: 0,
+            objects: Default::default(),
+            ops: Vec::new(),
+            cells: Default::default(),
+            storage: None,
+        });
+
+        let id = self.next_id;
+        self.next_id += 1;
+        frame.id = id;
+
+        if self.frames.is_empty() {
+            self.base = Some(objects.clone());
+        }
+        self.frames.push(frame);
+        GridSnapshot(id)
+    }
+
+    pub(crate) fn restore(
+        &mut self,
+        snapshot: GridSnapshot,
+        storage: &mut SparseStorage<GridCell<V2>>,
+        objects: &mut GridObjects<O, V2>,
+    ) -> bool {
+        let k = match self.frames.binary_search_by_key(&snapshot.0, |f| f.id) {
+            Ok(k) => k,
+            Err(_) => return false,
+        };
+
+        // Undo the frames from the most recent to the restored one, so that the oldest saved content wins
+        for frame in self.frames[k..].iter_mut().rev() {
+            frame.undo_cells(storage);
+        }
+
+        if self.frames[k..].iter().all(|f| f.ops.is_empty()) {
+            // The handles did not change, the objects can be restored in place
+            for frame in self.frames[k..].iter().rev() {
+                for (&handle, obj) in frame.objects.iter() {
+                    if let Some(obj) = obj {
+                        objects[handle] = *obj;
+                    }
+                }
+            }
+        } else {
+            let mut restored = self
+                .base
+                .clone()
+                .expect("the base is kept while there are snapshots");
+            for frame in &self.frames[..k] {
+                replay(&mut restored, &frame.ops);
+            }
+            // Objects that were not modified since the snapshot are the same as now
+            for (handle, obj) in restored.iter_mut() {
+                if let Some(current) = objects.get(handle) {
+                    *obj = *current;
+                }
+            }
+            for frame in self.frames[k..].iter().rev() {
+                for (&handle, obj) in frame.objects.iter() {
+                    if let (Some(obj), Some(slot)) = (obj, restored.get_mut(handle)) {
+                        *slot = *obj;
+                    }
+                }
+            }
+            *objects = restored;
+        }
+
+        while self.frames.len() > k + 1 {
+            let mut frame = self.frames.pop().unwrap();
+            frame.clear();
+            self.pool.push(frame);
+        }
+        self.frames[k].clear();
+
+        true
+    }
+
+    /// Forgets the snapshots taken before the given one, and all of them if it is None.
+    pub(crate) fn forget_before(&mut self, snapshot: Option<GridSnapshot>) {
+        let k = match snapshot {
+            Some(snapshot) => self.frames.partition_point(|f| f.id < snapshot.0),
+            None => self.frames.len(),
+        };
+
+        // Older frames are never needed to restore newer ones, only their insertions and removals are
+        // applied to the base so that it stays at the oldest live snapshot
+        for mut frame in self.frames.drain(..k) {
+            if let Some(base) = &mut self.base {
+                replay(base, &frame.ops);
+            }
+            frame.clear();
+            self.pool.push(frame);
+        }
+        if self.frames.is_empty() {
+            self.base = None;
+        }
+    }
+}
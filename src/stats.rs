@@ -0,0 +1,88 @@
+use std::fmt::{Display, Formatter};
+
+/// Occupancy statistics of a grid, returned by `Grid::stats` and `AABBGrid::stats`.
+/// Useful to check if the cell size is adapted to the problem.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GridStats {
+    /// Number of allocated cells
+    pub cells: usize,
+    /// Number of objects
+    pub objects: usize,
+    /// Number of cell entries, objects spanning multiple cells are counted once per cell
+    pub entries: usize,
+    /// Number of cells per occupancy bucket.
+    /// Bucket 0 counts the empty cells and bucket k counts the cells with an occupancy in [2^(k-1), 2^k).
+    pub histogram: Vec<usize>,
+    /// Maximum number of entries in a single cell
+    pub max_occupancy: usize,
+    /// Mean number of entries per cell
+    pub mean_occupancy: f32,
+    /// Number of cells waiting for a maintain()
+    pub dirty_cells: usize,
+    /// Ratio of objects spanning multiple cells, always 0 for a `Grid`
+    pub multi_cell_ratio: f32,
+    /// Estimated memory usage of the grid in bytes
+    pub memory_bytes: usize,
+}
+
+impl GridStats {
+    /// Builds the cell statistics from the occupancy and dirtiness of every cell.
+    pub(crate) fn from_cells(cells: impl Iterator<Item = (usize, bool)>) -> Self {
+        let mut stats = Self::default();
+
+        for (occupancy, dirty) in cells {
+            let bucket = (usize::BITS - occupancy.leading_zeros()) as usize;
+            if stats.histogram.len() <= bucket {
+                stats.histogram.resize(bucket + 1, 0);
+            }
+            stats.histogram[bucket] += 1;
+
+            stats.cells += 1;
+            stats.entries += occupancy;
+            stats.max_occupancy = stats.max_occupancy.max(occupancy);
+            stats.dirty_cells += dirty as usize;
+        }
+
+        if stats.cells > 0 {
+            stats.mean_occupancy = stats.entries as f32 / stats.cells as f32;
+        }
+
+        stats
+    }
+}
+
+impl Display for GridStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "cells: {} ({} dirty), objects: {}, entries: {}",
+            self.cells, self.dirty_cells, self.objects, self.entries
+        )?;
+        writeln!(
+            f,
+            "occupancy: mean {:.2}, max {}",
+            self.mean_occupancy, self.max_occupancy
+        )?;
+        writeln!(
+            f,
+            "multi-cell objects: {:.2}%",
+            self.multi_cell_ratio * 100.0
+        )?;
+        writeln!(f, "memory: ~{:.1} KiB", self.memory_bytes as f32 / 1024.0)?;
+        write!(f, "histogram:")?;
+        for (bucket, count) in self.histogram.iter().enumerate() {
+            match bucket {
+                0 => write!(f, "\n  0: {}", count)?,
+                1 => write!(f, "\n  1: {}", count)?,
+                _ => write!(
+                    f,
+                    "\n  {}-{}: {}",
+                    1usize << (bucket - 1),
+                    (1usize << bucket) - 1,
+                    count
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
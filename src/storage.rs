@@ -1,3 +1,4 @@
+use crate::heatmap::Heatmap;
 use crate::Vec2;
 
 pub type CellIdx = (i32, i32);
@@ -21,6 +22,116 @@ pub(crate) fn cell_range((min_x, min_y): CellIdx, (max_x, max_y): CellIdx) -> XY
     }
 }
 
+/// Number of cells in the range, which does not fit in 64 bits for extreme ranges.
+pub(crate) fn cell_count((min_x, min_y): CellIdx, (max_x, max_y): CellIdx) -> u128 {
+    if min_x > max_x || min_y > max_y {
+        return 0;
+    }
+    (max_x as i64 - min_x as i64 + 1) as u128 * (max_y as i64 - min_y as i64 + 1) as u128
+}
+
+pub(crate) fn cell_id<V2: Vec2>(cell_size: i32, pos: V2) -> CellIdx {
+    (
+        pos.x() as i32 / cell_size - if pos.x() < 0.0 { 1 } else { 0 },
+        pos.y() as i32 / cell_size - if pos.y() < 0.0 { 1 } else { 0 },
+    )
+}
+
+/// Whether `id` is the first cell shared by two ranges of cells given their lower left cells.
+/// Objects spanning multiple cells are found once per cell, so they are only reported in this one.
+pub(crate) fn first_shared_cell(ll_a: CellIdx, ll_b: CellIdx, id: CellIdx) -> bool {
+    (ll_a.0.max(ll_b.0), ll_a.1.max(ll_b.1)) == id
+}
+
+/// Lower left and upper right corners of a cell.
+/// Cells include one of their borders depending on the sign, so both borders are included to be conservative.
+pub(crate) fn cell_bounds(cell_size: i32, (x, y): CellIdx) -> ([f32; 2], [f32; 2]) {
+    let size = cell_size as f32;
+    let ll = [x as f32 * size, y as f32 * size];
+    (ll, [ll[0] + size, ll[1] + size])
+}
+
+/// Cells covered by a convex polygon, row by row from the bottom, as `(y, min_x, max_x)`.
+/// The range of each row is found by clipping the edges of the polygon to the row.
+pub(crate) fn polygon_rows(cell_size: i32, polygon: &[[f32; 2]]) -> Vec<(i32, i32, i32)> {
+    if polygon.is_empty() {
+        return vec![];
+    }
+
+    let min_y = polygon.iter().fold(f32::INFINITY, |m, p| m.min(p[1]));
+    let max_y = polygon.iter().fold(f32::NEG_INFINITY, |m, p| m.max(p[1]));
+    let size = cell_size as f32;
+
+    (cell_id(cell_size, [0.0, min_y]).1..=cell_id(cell_size, [0.0, max_y]).1)
+        .filter_map(|y| {
+            // Cells include one of their borders depending on the sign, so clip to both
+            let y0 = y as f32 * size;
+            let y1 = y0 + size;
+
+            let mut min_x = f32::INFINITY;
+            let mut max_x = f32::NEG_INFINITY;
+            for (i, &a) in polygon.iter().enumerate() {
+                let b = polygon[(i + 1) % polygon.len()];
+                let (a, b) = if a[1] <= b[1] { (a, b) } else { (b, a) };
+                if b[1] < y0 || a[1] > y1 {
+                    continue;
+                }
+
+                let dy = b[1] - a[1];
+                let (xa, xb) = if dy == 0.0 {
+                    (a[0], b[0])
+                } else {
+                    let x_at = |y: f32| a[0] + (b[0] - a[0]) * ((y - a[1]) / dy);
+                    (x_at(a[1].max(y0)), x_at(b[1].min(y1)))
+                };
+                min_x = min_x.min(xa).min(xb);
+                max_x = max_x.max(xa).max(xb);
+            }
+
+            if min_x > max_x {
+                return None;
+            }
+            Some((
+                y,
+                cell_id(cell_size, [min_x, 0.0]).0,
+                cell_id(cell_size, [max_x, 0.0]).0,
+            ))
+        })
+        .collect()
+}
+
+/// Suggests a cell size from the occupancy of the current cells, aiming for `TARGET_OCCUPANCY` objects per cell.
+/// If query extents are given, the suggestion is pulled towards their median, as queries should be about the same size as cells.
+pub(crate) fn suggest_cell_size(
+    cell_size: i32,
+    n_cells: usize,
+    n_entries: usize,
+    query_extents: &[f32],
+) -> i32 {
+    const TARGET_OCCUPANCY: f32 = 16.0;
+
+    if n_cells == 0 || n_entries == 0 {
+        return cell_size;
+    }
+
+    // Assuming the density is locally uniform, the occupancy grows with the area of the cells
+    let occupancy = n_entries as f32 / n_cells as f32;
+    let mut suggested = cell_size as f32 * (TARGET_OCCUPANCY / occupancy).sqrt();
+
+    let mut extents: Vec<f32> = query_extents
+        .iter()
+        .copied()
+        .filter(|x| x.is_finite() && *x > 0.0)
+        .collect();
+    if !extents.is_empty() {
+        let mid = extents.len() / 2;
+        let (_, median, _) = extents.select_nth_unstable_by(mid, |a, b| a.partial_cmp(b).unwrap());
+        suggested = (suggested * *median).sqrt();
+    }
+
+    (suggested.round() as i32).max(1)
+}
+
 /// `SparseStorage` stores cells in a `FastMap` to be used in a Grid.
 /// It is Sparse because cells are eagerly allocated, and cleaned when they are empty.
 /// It implements the Storage trait.
@@ -52,6 +163,10 @@ impl<T: Default> SparseStorage<T> {
         self.cells.retain(move |_, cell| !f(cell));
     }
 
+    pub fn modify_with_id(&mut self, mut f: impl FnMut(CellIdx, &mut T) -> bool) {
+        self.cells.retain(move |id, cell| !f(*id, cell));
+    }
+
     pub fn cell_mut<V2: Vec2>(&mut self, pos: V2) -> (CellIdx, &mut T) {
         let id = self.cell_id(pos);
         (id, self.cells.entry(id).or_default())
@@ -66,10 +181,43 @@ impl<T: Default> SparseStorage<T> {
     }
 
     pub fn cell_id<V2: Vec2>(&self, pos: V2) -> CellIdx {
-        (
-            pos.x() as i32 / self.cell_size - if pos.x() < 0.0 { 1 } else { 0 },
-            pos.y() as i32 / self.cell_size - if pos.y() < 0.0 { 1 } else { 0 },
-        )
+        cell_id(self.cell_size, pos)
+    }
+
+    /// Rasterizes the number of objects of each cell between the ll and ur cells (inclusive) into a `Heatmap`,
+    /// using `count` to get the number of objects of a cell. Missing cells count as zero.
+    pub fn rasterize(&self, ll: CellIdx, ur: CellIdx, count: impl Fn(&T) -> u32) -> Heatmap {
+        if ll.0 > ur.0 || ll.1 > ur.1 {
+            return Heatmap {
+                origin: ll,
+                ..Default::default()
+            };
+        }
+
+        let width = (ur.0 as i64 - ll.0 as i64 + 1) as usize;
+        let height = (ur.1 as i64 - ll.1 as i64 + 1) as usize;
+        let mut counts = vec![0; width * height];
+
+        if self.cells.len() < counts.len() {
+            for (&(x, y), cell) in self.cells.iter() {
+                if (ll.0..=ur.0).contains(&x) && (ll.1..=ur.1).contains(&y) {
+                    counts[(y - ll.1) as usize * width + (x - ll.0) as usize] = count(cell);
+                }
+            }
+        } else {
+            for (i, id) in cell_range(ll, ur).enumerate() {
+                if let Some(cell) = self.cell(id) {
+                    counts[i] = count(cell);
+                }
+            }
+        }
+
+        Heatmap {
+            origin: ll,
+            width,
+            height,
+            counts,
+        }
     }
 }
 
@@ -91,12 +239,35 @@ impl Iterator for XYRange {
         }
 
         let v = (self.x, self.y);
-        self.x += 1;
-        if self.x > self.max_x {
+        if self.x < self.max_x {
+            self.x += 1;
+        } else if self.y < self.max_y {
             self.x = self.min_x;
             self.y += 1;
+        } else {
+            // Done, without overflowing on ranges that end at i32::MAX
+            *self = cell_range((1, 1), (0, 0));
         }
 
         Some(v)
     }
 }
+
+/// Iterates over the runs of consecutive elements sharing the same cell id in a slice sorted by cell id.
+pub(crate) struct GroupByCell<'a, T>(pub &'a [(CellIdx, T)]);
+
+impl<'a, T> Iterator for GroupByCell<'a, T> {
+    type Item = &'a [(CellIdx, T)];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let id = self.0.first()?.0;
+        let end = self
+            .0
+            .iter()
+            .position(|x| x.0 != id)
+            .unwrap_or(self.0.len());
+        let (group, rest) = self.0.split_at(end);
+        self.0 = rest;
+        Some(group)
+    }
+}
@@ -0,0 +1,194 @@
+//! Debug helpers rendering the contents of a grid into a standalone SVG string that can be opened in a browser.
+//!
+//! The cells are drawn as gray squares, the objects as blue points or boxes, and if a query is given,
+//! its cell footprint is drawn in yellow, its rectangle in orange and its hits in red.
+//!
+//! ```rust
+//! use flat_spatial::Grid;
+//! use flat_spatial::svg::{grid_svg, SvgQuery};
+//!
+//! let mut g: Grid<(), [f32; 2]> = Grid::new(10);
+//! g.insert([3.0, 3.0], ());
+//! g.insert([12.0, -8.0], ());
+//!
+//! let hits: Vec<_> = g.query_around([2.0, 2.0], 5.0).map(|(id, _)| id).collect();
+//! let svg = grid_svg(&g, Some(SvgQuery { ll: [-3.0, -3.0], ur: [7.0, 7.0], hits: &hits }));
+//! assert!(svg.starts_with("<svg"));
+//! ```
+
+use crate::aabbgrid::AABBGridHandle;
+use crate::grid::GridHandle;
+use crate::storage::{cell_range, CellIdx};
+use crate::{AABBGrid, Grid, Vec2, AABB};
+use std::fmt::Write;
+
+/// A query to draw on top of the grid: the rectangle defined by lower left (ll) and upper right (ur),
+/// the cells it covers and the objects it returned.
+#[derive(Clone, Copy, Debug)]
+pub struct SvgQuery<'a, V2, H> {
+    pub ll: V2,
+    pub ur: V2,
+    pub hits: &'a [H],
+}
+
+/// Renders the cells and points of a `Grid`, and optionally a query.
+pub fn grid_svg<O: Copy, V2: Vec2>(
+    grid: &Grid<O, V2>,
+    query: Option<SvgQuery<'_, V2, GridHandle>>,
+) -> String {
+    let storage = grid.storage();
+    let mut svg = SvgWriter::new(storage.cell_size());
+
+    for (id, cell) in storage.cells.iter() {
+        if !cell.objs.is_empty() {
+            svg.cell(*id, "#eee");
+        }
+    }
+
+    if let Some(q) = query {
+        svg.footprint(storage.cell_id(q.ll), storage.cell_id(q.ur));
+    }
+
+    for (pos, _) in grid.objects() {
+        svg.point(pos, "#36c");
+    }
+
+    if let Some(q) = query {
+        svg.rect(q.ll, q.ur, "none", "#f80");
+        for (pos, _) in q.hits.iter().filter_map(|h| grid.get(*h)) {
+            svg.point(pos, "#d22");
+        }
+    }
+
+    svg.finish()
+}
+
+/// Renders the cells and boxes of an `AABBGrid`, and optionally a query.
+pub fn aabbgrid_svg<O: Copy, AB: AABB>(
+    grid: &AABBGrid<O, AB>,
+    query: Option<SvgQuery<'_, AB::V2, AABBGridHandle>>,
+) -> String {
+    let storage = grid.storage();
+    let mut svg = SvgWriter::new(storage.cell_size());
+
+    for (id, cell) in storage.cells.iter() {
+        if !cell.objs.is_empty() {
+            svg.cell(*id, "#eee");
+        }
+    }
+
+    if let Some(q) = query {
+        svg.footprint(storage.cell_id(q.ll), storage.cell_id(q.ur));
+    }
+
+    for h in grid.handles() {
+        let aabb = grid.get(h).unwrap().aabb;
+        svg.rect(aabb.ll(), aabb.ur(), "#36c4", "#36c");
+    }
+
+    if let Some(q) = query {
+        svg.rect(q.ll, q.ur, "none", "#f80");
+        for obj in q.hits.iter().filter_map(|h| grid.get(*h)) {
+            svg.rect(obj.aabb.ll(), obj.aabb.ur(), "#d224", "#d22");
+        }
+    }
+
+    svg.finish()
+}
+
+/// Accumulates the svg elements while keeping track of the drawn area, as the view box is only known at the end.
+struct SvgWriter {
+    cell_size: i32,
+    body: String,
+    min: [f32; 2],
+    max: [f32; 2],
+}
+
+impl SvgWriter {
+    fn new(cell_size: i32) -> Self {
+        Self {
+            cell_size,
+            body: String::new(),
+            min: [f32::INFINITY; 2],
+            max: [f32::NEG_INFINITY; 2],
+        }
+    }
+
+    fn extend_bounds(&mut self, ll: [f32; 2], ur: [f32; 2]) {
+        self.min = [self.min[0].min(ll[0]), self.min[1].min(ll[1])];
+        self.max = [self.max[0].max(ur[0]), self.max[1].max(ur[1])];
+    }
+
+    fn cell(&mut self, (x, y): CellIdx, fill: &str) {
+        let size = self.cell_size as f32;
+        let ll = [x as f32 * size, y as f32 * size];
+        let ur = [ll[0] + size, ll[1] + size];
+        self.extend_bounds(ll, ur);
+        let _ = writeln!(
+            self.body,
+            r##"<rect x="{}" y="{}" width="{}" height="{}" fill="{}" stroke="#ccc" vector-effect="non-scaling-stroke"/>"##,
+            ll[0], ll[1], size, size, fill
+        );
+    }
+
+    fn footprint(&mut self, ll_id: CellIdx, ur_id: CellIdx) {
+        for id in cell_range(ll_id, ur_id) {
+            self.cell(id, "#fd48")
+        }
+    }
+
+    fn point(&mut self, pos: impl Vec2, fill: &str) {
+        let p = [pos.x(), pos.y()];
+        self.extend_bounds(p, p);
+        let _ = writeln!(
+            self.body,
+            r#"<circle cx="{}" cy="{}" r="{}" fill="{}"/>"#,
+            p[0],
+            p[1],
+            self.cell_size as f32 * 0.05,
+            fill
+        );
+    }
+
+    fn rect(&mut self, ll: impl Vec2, ur: impl Vec2, fill: &str, stroke: &str) {
+        let (ll, ur) = (
+            [ll.x().min(ur.x()), ll.y().min(ur.y())],
+            [ll.x().max(ur.x()), ll.y().max(ur.y())],
+        );
+        self.extend_bounds(ll, ur);
+        let _ = writeln!(
+            self.body,
+            r##"<rect x="{}" y="{}" width="{}" height="{}" fill="{}" stroke="{}" vector-effect="non-scaling-stroke"/>"##,
+            ll[0],
+            ll[1],
+            ur[0] - ll[0],
+            ur[1] - ll[1],
+            fill,
+            stroke
+        );
+    }
+
+    fn finish(self) -> String {
+        let (min, max) = if self.min[0] <= self.max[0] {
+            (self.min, self.max)
+        } else {
+            ([0.0; 2], [self.cell_size as f32; 2])
+        };
+        let margin = self.cell_size as f32 * 0.5;
+        let (x, y) = (min[0] - margin, min[1] - margin);
+        let (w, h) = (
+            max[0] - min[0] + 2.0 * margin,
+            max[1] - min[1] + 2.0 * margin,
+        );
+
+        // The y axis is flipped so that y goes up, like in the grid
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n<g transform=\"scale(1,-1)\">\n{}</g>\n</svg>\n",
+            x,
+            -(y + h),
+            w,
+            h,
+            self.body
+        )
+    }
+}
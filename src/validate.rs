@@ -0,0 +1,99 @@
+use crate::storage::CellIdx;
+use std::fmt::{Debug, Display, Formatter};
+
+/// Validation stops after finding this many errors, so corrupted data cannot make it allocate without bounds.
+pub const MAX_ERRORS: usize = 100;
+
+/// A broken invariant found by `Grid::validate` or `AABBGrid::validate`.
+/// A grid with such errors can lead to wrong query results or undefined behavior.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValidationError<H> {
+    /// A cell contains a handle that does not point to a live object
+    DeadHandle { cell: CellIdx, handle: H },
+    /// An object is stored in a cell it should not be in
+    WrongCell { cell: CellIdx, handle: H },
+    /// An object is missing from a cell it should be in
+    MissingFromCell { cell: CellIdx, handle: H },
+    /// An object is stored more than once in the same cell
+    Duplicate { cell: CellIdx, handle: H },
+    /// The position stored in the cell is not the position of the object
+    PositionMismatch { cell: CellIdx, handle: H },
+    /// The single cell flag stored in the cell does not match the aabb of the object
+    WrongSingleCellFlag { cell: CellIdx, handle: H },
+    /// An object has a pending update but its cell is not marked dirty, so maintain() would miss it
+    NotDirty { cell: CellIdx, handle: H },
+    /// An object has a cell id or a pending update that is inconsistent with its position
+    InvalidState { handle: H },
+}
+
+impl<H: Debug> Display for ValidationError<H> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::DeadHandle { cell, handle } => {
+                write!(f, "cell {:?} contains dead handle {:?}", cell, handle)
+            }
+            ValidationError::WrongCell { cell, handle } => {
+                write!(f, "object {:?} should not be in cell {:?}", handle, cell)
+            }
+            ValidationError::MissingFromCell { cell, handle } => {
+                write!(f, "object {:?} is missing from cell {:?}", handle, cell)
+            }
+            ValidationError::Duplicate { cell, handle } => {
+                write!(f, "object {:?} is duplicated in cell {:?}", handle, cell)
+            }
+            ValidationError::PositionMismatch { cell, handle } => write!(
+                f,
+                "position of object {:?} in cell {:?} does not match",
+                handle, cell
+            ),
+            ValidationError::WrongSingleCellFlag { cell, handle } => write!(
+                f,
+                "single cell flag of object {:?} in cell {:?} does not match its aabb",
+                handle, cell
+            ),
+            ValidationError::NotDirty { cell, handle } => write!(
+                f,
+                "object {:?} has a pending update but cell {:?} is not dirty",
+                handle, cell
+            ),
+            ValidationError::InvalidState { handle } => {
+                write!(f, "object {:?} has an inconsistent pending update", handle)
+            }
+        }
+    }
+}
+
+impl<H: Debug> std::error::Error for ValidationError<H> {}
+
+/// Error returned when deserializing a grid whose internals are inconsistent,
+/// for example because the data was truncated or tampered with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CorruptedGrid<H> {
+    /// The cell size is less than or equal to zero
+    InvalidCellSize(i32),
+    /// The cell keys or the objects of a frozen grid or an archive are not sorted,
+    /// or its cell offsets or object indices are out of bounds
+    InvalidLayout,
+    /// The cells do not match the objects
+    Invalid(Vec<ValidationError<H>>),
+}
+
+impl<H: Debug> Display for CorruptedGrid<H> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CorruptedGrid::InvalidCellSize(cell_size) => {
+                write!(f, "invalid cell size: {}", cell_size)
+            }
+            CorruptedGrid::InvalidLayout => write!(f, "malformed cell keys or offsets"),
+            CorruptedGrid::Invalid(errors) => {
+                write!(f, "corrupted grid with {} broken invariants", errors.len())?;
+                if let Some(first) = errors.first() {
+                    write!(f, ", first: {}", first)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<H: Debug> std::error::Error for CorruptedGrid<H> {}
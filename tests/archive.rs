@@ -0,0 +1,184 @@
+#![cfg(feature = "rkyv")]
+
+//! Archives are tampered with at the byte level, which `check_archived_root` accepts
+//! but `validate` must reject.
+
+use flat_spatial::archive::{AABBGridData, FrozenGridData};
+use flat_spatial::shape::{BoundingBox, Circle, Shape};
+use flat_spatial::validate::{CorruptedGrid, ValidationError};
+use flat_spatial::{AABBGrid, Grid, AABB};
+
+/// Replaces the only occurrence of `from` in `bytes` by `to`
+fn replace(bytes: &mut [u8], from: &[u8], to: &[u8]) {
+    let starts: Vec<_> = (0..=bytes.len() - from.len())
+        .filter(|&i| &bytes[i..i + from.len()] == from)
+        .collect();
+    assert_eq!(starts.len(), 1, "pattern is not unique");
+    bytes[starts[0]..starts[0] + to.len()].copy_from_slice(to);
+}
+
+fn u32s(xs: &[u32]) -> Vec<u8> {
+    xs.iter().flat_map(|x| x.to_ne_bytes()).collect()
+}
+
+/// a at (3, 3) in cell (0, 0), b at (13, 3) in cell (1, 0), so offsets are [0, 1, 2]
+fn frozen_bytes() -> (rkyv::AlignedVec, flat_spatial::grid::GridHandle) {
+    let mut g: Grid<u32, [f32; 2]> = Grid::new(10);
+    g.insert([3.0, 3.0], 1);
+    let b = g.insert([13.0, 3.0], 2);
+    let bytes = rkyv::to_bytes::<_, 256>(&FrozenGridData::from(&g.freeze())).unwrap();
+    (bytes, b)
+}
+
+/// a covers cell (0, 0), b covers cells (0, 0) and (1, 0), so offsets are [0, 2, 3]
+fn aabbgrid_bytes() -> (rkyv::AlignedVec, flat_spatial::aabbgrid::AABBGridHandle) {
+    let mut g: AABBGrid<u32, BoundingBox<[f32; 2]>> = AABBGrid::new(10);
+    g.insert(BoundingBox::new([1.0, 1.0], [2.0, 2.0]), 1);
+    let b = g.insert(BoundingBox::new([8.0, 1.0], [12.0, 2.0]), 2);
+    let bytes = rkyv::to_bytes::<_, 256>(&AABBGridData::from(&g)).unwrap();
+    (bytes, b)
+}
+
+#[test]
+fn untouched_archives_are_valid() {
+    let (bytes, _) = frozen_bytes();
+    let archived = rkyv::check_archived_root::<FrozenGridData<u32>>(&bytes).unwrap();
+    assert_eq!(archived.validate(), Ok(()));
+
+    let (bytes, _) = aabbgrid_bytes();
+    let archived = rkyv::check_archived_root::<AABBGridData<u32>>(&bytes).unwrap();
+    assert_eq!(archived.validate(), Ok(()));
+}
+
+#[test]
+fn frozen_offsets_out_of_bounds() {
+    let (mut bytes, _) = frozen_bytes();
+    replace(&mut bytes, &u32s(&[0, 1, 2]), &u32s(&[0, 1, 3]));
+    let archived = rkyv::check_archived_root::<FrozenGridData<u32>>(&bytes).unwrap();
+    assert_eq!(archived.validate(), Err(CorruptedGrid::InvalidLayout));
+}
+
+#[test]
+fn frozen_offsets_not_monotonic() {
+    let (mut bytes, _) = frozen_bytes();
+    replace(&mut bytes, &u32s(&[0, 1, 2]), &u32s(&[0, 3, 2]));
+    let archived = rkyv::check_archived_root::<FrozenGridData<u32>>(&bytes).unwrap();
+    assert_eq!(archived.validate(), Err(CorruptedGrid::InvalidLayout));
+}
+
+#[test]
+fn frozen_wrong_cell() {
+    let (mut bytes, b) = frozen_bytes();
+    // b is moved to the end of the first cell
+    replace(&mut bytes, &u32s(&[0, 1, 2]), &u32s(&[0, 2, 2]));
+    let archived = rkyv::check_archived_root::<FrozenGridData<u32>>(&bytes).unwrap();
+    assert_eq!(
+        archived.validate(),
+        Err(CorruptedGrid::Invalid(vec![ValidationError::WrongCell {
+            cell: (0, 0),
+            handle: b,
+        }]))
+    );
+}
+
+#[test]
+fn aabbgrid_offsets_out_of_bounds() {
+    let (mut bytes, _) = aabbgrid_bytes();
+    replace(&mut bytes, &u32s(&[0, 2, 3]), &u32s(&[0, 2, 4]));
+    let archived = rkyv::check_archived_root::<AABBGridData<u32>>(&bytes).unwrap();
+    assert_eq!(archived.validate(), Err(CorruptedGrid::InvalidLayout));
+}
+
+#[test]
+fn aabbgrid_duplicate() {
+    let (mut bytes, b) = aabbgrid_bytes();
+    // The cells become [a] and [b, b]
+    replace(&mut bytes, &u32s(&[0, 2, 3]), &u32s(&[0, 1, 3]));
+    let archived = rkyv::check_archived_root::<AABBGridData<u32>>(&bytes).unwrap();
+    assert_eq!(
+        archived.validate(),
+        Err(CorruptedGrid::Invalid(vec![
+            ValidationError::Duplicate {
+                cell: (1, 0),
+                handle: b,
+            },
+            ValidationError::MissingFromCell {
+                cell: (0, 0),
+                handle: b,
+            },
+        ]))
+    );
+}
+
+#[test]
+fn aabbgrid_object_index_out_of_bounds() {
+    let (mut bytes, _) = aabbgrid_bytes();
+    // Entries are (object index, single cell flag) padded to 8 bytes
+    let entry = |object: u32, sing_cell: bool| {
+        let mut e = object.to_ne_bytes().to_vec();
+        e.extend_from_slice(&[sing_cell as u8, 0, 0, 0]);
+        e
+    };
+    let entries = [entry(0, true), entry(1, false), entry(1, false)].concat();
+    let tampered = [entry(0, true), entry(1, false), entry(7, false)].concat();
+    replace(&mut bytes, &entries, &tampered);
+    let archived = rkyv::check_archived_root::<AABBGridData<u32>>(&bytes).unwrap();
+    assert_eq!(archived.validate(), Err(CorruptedGrid::InvalidLayout));
+}
+
+#[test]
+fn aabbgrid_huge_bounds_are_reported_once() {
+    let (mut bytes, b) = aabbgrid_bytes();
+    let f32s = |xs: &[f32]| -> Vec<u8> { xs.iter().flat_map(|x| x.to_ne_bytes()).collect() };
+    replace(
+        &mut bytes,
+        &f32s(&[8.0, 1.0, 12.0, 2.0]),
+        &f32s(&[-1e9, -1e9, 1e9, 1e9]),
+    );
+    let archived = rkyv::check_archived_root::<AABBGridData<u32>>(&bytes).unwrap();
+    assert_eq!(
+        archived.validate(),
+        Err(CorruptedGrid::Invalid(vec![
+            ValidationError::MissingFromCell {
+                cell: (-100_000_001, -100_000_001),
+                handle: b,
+            }
+        ]))
+    );
+}
+
+#[test]
+fn circle_grid_queries_match_live_grid() {
+    let rng = fastrand::Rng::with_seed(3);
+    let circle = || {
+        let center = [rng.f32() * 200.0 - 100.0, rng.f32() * 200.0 - 100.0];
+        Circle::new(center, rng.f32() * 8.0)
+    };
+
+    let mut g: AABBGrid<u32, Circle<[f32; 2]>> = AABBGrid::new(10);
+    for i in 0..500 {
+        g.insert(circle(), i);
+    }
+    let bytes = rkyv::to_bytes::<_, 256>(&AABBGridData::from(&g)).unwrap();
+    let archived = rkyv::check_archived_root::<AABBGridData<u32>>(&bytes).unwrap();
+    assert_eq!(archived.validate(), Ok(()));
+
+    // Objects whose bounding box intersects the query but not their circle, which must not be reported
+    let mut bbox_only = 0;
+    for _ in 0..500 {
+        let q = circle();
+        let mut live: Vec<_> = g.query(q).map(|(h, _, _)| h).collect();
+        let mut arch: Vec<_> = archived.query(q).map(|(h, _, _, _)| h).collect();
+        live.sort();
+        arch.sort();
+        assert_eq!(live, arch);
+
+        let qbox = q.bounding_aabb();
+        bbox_only += g
+            .handles()
+            .filter(|&h| qbox.intersects(&g.get(h).unwrap().aabb.bounding_aabb()))
+            .count()
+            - live.len();
+    }
+    assert!(bbox_only > 0);
+}
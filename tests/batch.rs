@@ -0,0 +1,59 @@
+use flat_spatial::batch::BatchResults;
+use flat_spatial::shape::BoundingBox;
+use flat_spatial::{AABBGrid, Grid};
+
+fn random_point(rng: &fastrand::Rng) -> [f32; 2] {
+    [rng.f32() * 200.0 - 100.0, rng.f32() * 200.0 - 100.0]
+}
+
+fn sorted<T: Ord>(mut v: Vec<T>) -> Vec<T> {
+    v.sort();
+    v
+}
+
+#[test]
+fn query_around_batch_matches_query_around() {
+    let rng = fastrand::Rng::with_seed(1);
+    let mut g: Grid<(), [f32; 2]> = Grid::new(10);
+    for _ in 0..2000 {
+        g.insert(random_point(&rng), ());
+    }
+
+    let mut out = BatchResults::new();
+    for n in [0, 1, 500] {
+        let queries: Vec<_> = (0..n)
+            .map(|_| (random_point(&rng), rng.f32() * 25.0))
+            .collect();
+        g.query_around_batch(&queries, &mut out);
+
+        assert_eq!(out.len(), n);
+        for (i, &(pos, radius)) in queries.iter().enumerate() {
+            let expected: Vec<_> = g.query_around(pos, radius).map(|(h, _)| h).collect();
+            assert_eq!(sorted(out.get(i).to_vec()), sorted(expected));
+        }
+    }
+}
+
+#[test]
+fn query_batch_matches_query() {
+    let rng = fastrand::Rng::with_seed(2);
+    let random_box = |size: f32| {
+        let ll = random_point(&rng);
+        BoundingBox::new(ll, [ll[0] + rng.f32() * size, ll[1] + rng.f32() * size])
+    };
+
+    let mut g: AABBGrid<(), BoundingBox<[f32; 2]>> = AABBGrid::new(10);
+    for _ in 0..2000 {
+        g.insert(random_box(15.0), ());
+    }
+
+    let mut out = BatchResults::new();
+    let queries: Vec<_> = (0..500).map(|_| random_box(30.0)).collect();
+    g.query_batch(&queries, &mut out);
+
+    assert_eq!(out.len(), queries.len());
+    for (i, &q) in queries.iter().enumerate() {
+        let expected: Vec<_> = g.query(q).map(|(h, _, _)| h).collect();
+        assert_eq!(sorted(out.get(i).to_vec()), sorted(expected));
+    }
+}
@@ -0,0 +1,126 @@
+#![cfg(feature = "serde")]
+
+use flat_spatial::compact::MAX_SLOTS_PER_OBJECT;
+use flat_spatial::shape::BoundingBox;
+use flat_spatial::{AABBGrid, Grid};
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Points {
+    #[serde(with = "flat_spatial::compact")]
+    grid: Grid<u32, [f32; 2]>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Boxes {
+    #[serde(with = "flat_spatial::compact")]
+    grid: AABBGrid<u32, BoundingBox<[f32; 2]>>,
+}
+
+#[test]
+fn grid_round_trip_keeps_handles() {
+    let rng = fastrand::Rng::with_seed(1);
+    let mut g: Grid<u32, [f32; 2]> = Grid::new(10);
+    let mut handles = vec![];
+    // Churn so that handles have various versions and the slots have holes
+    for round in 0..10 {
+        for i in 0..100 {
+            handles.push(g.insert([rng.f32() * 100.0, rng.f32() * 100.0], round * 100 + i));
+        }
+        for _ in 0..60 {
+            let h = handles.swap_remove(rng.usize(..handles.len()));
+            g.remove(h);
+        }
+        g.maintain();
+    }
+    // Pending updates are applied when writing
+    let moved = handles[0];
+    g.set_position(moved, [-50.0, -50.0]);
+    let removed = handles.pop().unwrap();
+    g.remove(removed);
+
+    let data = serde_json::to_string(&Points { grid: g.clone() }).unwrap();
+    let loaded: Points = serde_json::from_str(&data).unwrap();
+    let mut loaded = loaded.grid;
+    g.maintain();
+
+    assert_eq!(loaded.validate(), Ok(()));
+    assert_eq!(loaded.len(), handles.len());
+    assert_eq!(loaded.get(moved).unwrap().0, [-50.0, -50.0]);
+    assert_eq!(loaded.get(removed), None);
+    for &h in &handles {
+        assert_eq!(loaded.get(h), g.get(h));
+    }
+
+    // New handles do not collide with the loaded ones
+    let h = loaded.insert([1.0, 1.0], 0);
+    assert!(!handles.contains(&h));
+    assert_eq!(loaded.len(), handles.len() + 1);
+    assert_eq!(loaded.validate(), Ok(()));
+}
+
+#[test]
+fn aabbgrid_round_trip_keeps_handles() {
+    let mut g: AABBGrid<u32, BoundingBox<[f32; 2]>> = AABBGrid::new(10);
+    let a = g.insert(BoundingBox::new([1.0, 1.0], [2.0, 2.0]), 1);
+    let b = g.insert(BoundingBox::new([8.0, 1.0], [12.0, 2.0]), 2);
+    let c = g.insert(BoundingBox::new([5.0, 5.0], [6.0, 6.0]), 3);
+    g.remove_lazy(a);
+    g.set_aabb_lazy(b, BoundingBox::new([20.0, 20.0], [31.0, 22.0]));
+
+    let data = serde_json::to_string(&Boxes { grid: g }).unwrap();
+    let loaded: Boxes = serde_json::from_str(&data).unwrap();
+    let g = loaded.grid;
+
+    assert_eq!(g.validate(), Ok(()));
+    assert_eq!(g.len(), 2);
+    assert!(g.get(a).is_none());
+    assert_eq!(
+        g.get(b).unwrap().aabb,
+        BoundingBox::new([20.0, 20.0], [31.0, 22.0])
+    );
+    assert_eq!(g.get(c).unwrap().obj, 3);
+}
+
+#[test]
+fn duplicated_handle_is_rejected() {
+    let mut g: Grid<u32, [f32; 2]> = Grid::new(10);
+    g.insert([1.0, 1.0], 1);
+    let data = serde_json::to_string(&Points { grid: g }).unwrap();
+
+    // [version, cell size, [[handle, pos, obj]]]
+    let mut value: serde_json::Value = serde_json::from_str(&data).unwrap();
+    let records = value["grid"][2].as_array_mut().unwrap();
+    records.push(records[0].clone());
+
+    let err = serde_json::from_value::<Points>(value).err().unwrap();
+    assert!(err.to_string().contains("duplicated handle"), "{}", err);
+}
+
+/// Compact data of a grid with one object for each handle, given as (index, version)
+fn with_handles(handles: &[(u32, u32)]) -> serde_json::Value {
+    let records: Vec<_> = handles
+        .iter()
+        .map(|&(idx, version)| {
+            serde_json::json!([{ "idx": idx, "version": version }, [1.0, 1.0], 1])
+        })
+        .collect();
+    serde_json::json!({ "grid": [1, 10, records] })
+}
+
+#[test]
+fn handles_beyond_the_limits_are_rejected() {
+    let limit = 16 * MAX_SLOTS_PER_OBJECT as u32;
+
+    let g = serde_json::from_value::<Points>(with_handles(&[(limit, 1), (3, 2 * limit + 1)]))
+        .unwrap()
+        .grid;
+    assert_eq!(g.len(), 2);
+
+    let too_far = with_handles(&[(limit + 1, 1)]);
+    let err = serde_json::from_value::<Points>(too_far).err().unwrap();
+    assert!(err.to_string().contains("too large"), "{}", err);
+
+    let too_old = with_handles(&[(1, u32::MAX)]);
+    let err = serde_json::from_value::<Points>(too_old).err().unwrap();
+    assert!(err.to_string().contains("reused"), "{}", err);
+}
@@ -0,0 +1,137 @@
+#![cfg(feature = "rayon")]
+
+use flat_spatial::aabbgrid::AABBGridHandle;
+use flat_spatial::grid::GridHandle;
+use flat_spatial::shape::BoundingBox;
+use flat_spatial::{AABBGrid, Grid};
+
+type Cells<T> = Vec<((i32, i32), Vec<T>)>;
+
+fn pos(rng: &fastrand::Rng) -> [f32; 2] {
+    [rng.f32() * 200.0 - 100.0, rng.f32() * 200.0 - 100.0]
+}
+
+fn grid_cells(g: &Grid<u32, [f32; 2]>) -> Cells<(GridHandle, [f32; 2])> {
+    let mut cells: Vec<_> = g
+        .storage()
+        .cells
+        .iter()
+        .map(|(&id, cell)| (id, cell.objs.clone()))
+        .collect();
+    cells.sort_by_key(|&(id, _)| id);
+    cells
+}
+
+fn aabb_cells(g: &AABBGrid<u32, BoundingBox<[f32; 2]>>) -> Cells<(AABBGridHandle, bool)> {
+    let mut cells: Vec<_> = g
+        .storage()
+        .cells
+        .iter()
+        .map(|(&id, cell)| (id, cell.objs.clone()))
+        .collect();
+    cells.sort_by_key(|&(id, _)| id);
+    cells
+}
+
+#[test]
+fn grid_par_maintain_matches_maintain() {
+    let rng = fastrand::Rng::with_seed(1);
+    let mut seq: Grid<u32, [f32; 2]> = Grid::new(10);
+    for i in 0..5000 {
+        seq.insert(pos(&rng), i);
+    }
+    let mut par = seq.clone();
+
+    for _ in 0..20 {
+        let handles: Vec<_> = seq.handles().collect();
+        for &h in &handles {
+            match rng.u32(0..10) {
+                0 => {
+                    seq.remove(h);
+                    par.remove(h);
+                }
+                1..=4 => {
+                    let p = pos(&rng);
+                    seq.set_position(h, p);
+                    par.set_position(h, p);
+                }
+                5 => {
+                    // Small moves that mostly stay in the same cell
+                    let (p, _) = seq.get(h).unwrap();
+                    let p = [p[0] + rng.f32() - 0.5, p[1] + rng.f32() - 0.5];
+                    seq.set_position(h, p);
+                    par.set_position(h, p);
+                }
+                _ => {}
+            }
+        }
+        for _ in 0..100 {
+            let p = pos(&rng);
+            assert_eq!(seq.insert(p, 0), par.insert(p, 0));
+        }
+
+        seq.maintain_deterministic();
+        par.par_maintain();
+
+        assert_eq!(grid_cells(&seq), grid_cells(&par));
+        assert_eq!(
+            seq.handles().collect::<Vec<_>>(),
+            par.handles().collect::<Vec<_>>()
+        );
+        for h in seq.handles() {
+            assert_eq!(seq.get(h), par.get(h));
+        }
+        assert!(par.validate().is_ok());
+    }
+}
+
+#[test]
+fn aabbgrid_par_maintain_matches_maintain() {
+    let rng = fastrand::Rng::with_seed(2);
+    let bbox = |rng: &fastrand::Rng| {
+        let ll = pos(rng);
+        BoundingBox::new(ll, [ll[0] + rng.f32() * 25.0, ll[1] + rng.f32() * 25.0])
+    };
+
+    let mut seq: AABBGrid<u32, BoundingBox<[f32; 2]>> = AABBGrid::new(10);
+    for i in 0..3000 {
+        seq.insert(bbox(&rng), i);
+    }
+    let mut par = seq.clone();
+
+    for _ in 0..20 {
+        let handles: Vec<_> = seq.handles().collect();
+        for &h in &handles {
+            match rng.u32(0..10) {
+                0 => {
+                    seq.remove_lazy(h);
+                    par.remove_lazy(h);
+                }
+                1..=4 => {
+                    let b = bbox(&rng);
+                    seq.set_aabb_lazy(h, b);
+                    par.set_aabb_lazy(h, b);
+                }
+                _ => {}
+            }
+        }
+        for _ in 0..100 {
+            let b = bbox(&rng);
+            assert_eq!(seq.insert(b, 0), par.insert(b, 0));
+        }
+
+        seq.maintain_deterministic();
+        par.par_maintain();
+
+        assert_eq!(aabb_cells(&seq), aabb_cells(&par));
+        assert_eq!(
+            seq.handles().collect::<Vec<_>>(),
+            par.handles().collect::<Vec<_>>()
+        );
+        for h in seq.handles() {
+            let (a, b) = (seq.get(h).unwrap(), par.get(h).unwrap());
+            assert_eq!((a.aabb, a.obj), (b.aabb, b.obj));
+        }
+        assert!(par.validate().is_ok());
+    }
+}
@@ -0,0 +1,592 @@
+//! Compares the queries that skip cells against a brute-force test on every object.
+//! Half of the coordinates are snapped to multiples of 5, so that objects and query borders lie on the
+//! boundaries of the cells. The objects that are within rounding errors of a border are not checked.
+
+use flat_spatial::aabbgrid::AABBGridHandle;
+use flat_spatial::grid::GridHandle;
+use flat_spatial::shape::BoundingBox;
+use flat_spatial::{AABBGrid, Grid};
+
+type Box2 = BoundingBox<[f32; 2]>;
+
+const CELL_SIZES: [i32; 3] = [3, 10, 64];
+
+fn snap(v: f32) -> f32 {
+    (v / 5.0).round() * 5.0
+}
+
+/// A point in [-150, 150], snapped with `snapped`
+fn random_point(rng: &fastrand::Rng, snapped: bool) -> [f32; 2] {
+    let p = [rng.f32() * 300.0 - 150.0, rng.f32() * 300.0 - 150.0];
+    if snapped {
+        [snap(p[0]), snap(p[1])]
+    } else {
+        p
+    }
+}
+
+fn random_grid(rng: &fastrand::Rng, cell_size: i32) -> Grid<(), [f32; 2]> {
+    let mut g = Grid::new(cell_size);
+    for _ in 0..1000 {
+        g.insert(random_point(rng, rng.bool()), ());
+    }
+    g
+}
+
+fn random_aabbgrid(rng: &fastrand::Rng, cell_size: i32) -> AABBGrid<(), Box2> {
+    let mut g = AABBGrid::new(cell_size);
+    for _ in 0..1000 {
+        let snapped = rng.bool();
+        let ll = random_point(rng, snapped);
+        let size = random_point(rng, snapped);
+        let size = [size[0].abs() / 8.0, size[1].abs() / 8.0];
+        let size = if snapped {
+            [snap(size[0]), snap(size[1])]
+        } else {
+            size
+        };
+        g.insert(BoundingBox::new(ll, [ll[0] + size[0], ll[1] + size[1]]), ());
+    }
+    g
+}
+
+fn points(g: &Grid<(), [f32; 2]>) -> Vec<(GridHandle, [f32; 2])> {
+    g.handles().map(|h| (h, g.get(h).unwrap().0)).collect()
+}
+
+fn boxes(g: &AABBGrid<(), Box2>) -> Vec<(AABBGridHandle, Box2)> {
+    g.handles().map(|h| (h, g.get(h).unwrap().aabb)).collect()
+}
+
+/// `a >= b`, or None if they are different but within `eps`
+fn ge(a: f64, b: f64, eps: f64) -> Option<bool> {
+    if a != b && (a - b).abs() < eps {
+        return None;
+    }
+    Some(a >= b)
+}
+
+/// `a < b`, or None if they are different but within `eps`
+fn lt(a: f64, b: f64, eps: f64) -> Option<bool> {
+    ge(a, b, eps).map(|x| !x)
+}
+
+/// All the conditions hold, or None if it depends on the ambiguous ones
+fn all(conditions: impl IntoIterator<Item = Option<bool>>) -> Option<bool> {
+    let mut result = Some(true);
+    for c in conditions {
+        match c {
+            Some(false) => return Some(false),
+            None => result = None,
+            Some(true) => {}
+        }
+    }
+    result
+}
+
+/// Checks that the found handles are the expected ones, ignoring the ambiguous ones.
+/// Returns the number of expected hits.
+fn assert_found<H: Copy + Ord + std::fmt::Debug>(
+    found: impl IntoIterator<Item = H>,
+    expected: impl IntoIterator<Item = (H, Option<bool>)>,
+    context: impl std::fmt::Debug,
+) -> usize {
+    let mut found: Vec<H> = found.into_iter().collect();
+    found.sort();
+    let n = found.len();
+    found.dedup();
+    assert_eq!(found.len(), n, "duplicated results for {:?}", context);
+
+    let mut n_hits = 0;
+    for (h, expected) in expected {
+        let is_found = found.binary_search(&h).is_ok();
+        match expected {
+            Some(true) => {
+                n_hits += 1;
+                assert!(is_found, "{:?} is missing for {:?}", h, context);
+            }
+            Some(false) => assert!(!is_found, "{:?} should not be found for {:?}", h, context),
+            None => {}
+        }
+    }
+    n_hits
+}
+
+/// A convex polygon, either snapped (a triangle or a rectangle) or with up to 8 vertices on an ellipse,
+/// in a random winding order
+fn random_polygon(rng: &fastrand::Rng) -> Vec<[f32; 2]> {
+    let mut polygon = if rng.bool() {
+        // Offsets of up to 60 from the first vertex
+        let near = |a: [f32; 2]| {
+            let d = random_point(rng, true);
+            [a[0] + snap(d[0] / 2.5), a[1] + snap(d[1] / 2.5)]
+        };
+        loop {
+            let a = random_point(rng, true);
+            let (b, c) = (near(a), near(a));
+            if rng.bool() {
+                if a[0] != b[0] && a[1] != b[1] {
+                    break vec![a, [b[0], a[1]], b, [a[0], b[1]]];
+                }
+                continue;
+            }
+            let area = (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0]);
+            if area != 0.0 {
+                break vec![a, b, c];
+            }
+        }
+    } else {
+        let center = random_point(rng, false);
+        let radii = [1.0 + rng.f32() * 80.0, 1.0 + rng.f32() * 80.0];
+        let rotation = rng.f32() * std::f32::consts::TAU;
+        let mut angles: Vec<f32> = (0..rng.usize(3..9))
+            .map(|_| rng.f32() * std::f32::consts::TAU)
+            .collect();
+        angles.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        angles
+            .into_iter()
+            .map(|a| {
+                let (x, y) = (radii[0] * a.cos(), radii[1] * a.sin());
+                let (sin, cos) = rotation.sin_cos();
+                [center[0] + x * cos - y * sin, center[1] + x * sin + y * cos]
+            })
+            .collect()
+    };
+    if rng.bool() {
+        polygon.reverse();
+    }
+    polygon
+}
+
+/// A radius of up to 80, snapped or not
+fn random_radius(rng: &fastrand::Rng) -> f32 {
+    let r = rng.f32() * 80.0;
+    if rng.bool() {
+        snap(r)
+    } else {
+        r
+    }
+}
+
+fn dist2(a: [f32; 2], b: [f32; 2]) -> f64 {
+    let (x, y) = (a[0] as f64 - b[0] as f64, a[1] as f64 - b[1] as f64);
+    x * x + y * y
+}
+
+fn edges(polygon: &[[f32; 2]]) -> impl Iterator<Item = ([f64; 2], [f64; 2])> + '_ {
+    let n = polygon.len();
+    (0..n).map(move |i| {
+        let (a, b) = (polygon[i], polygon[(i + 1) % n]);
+        ([a[0] as f64, a[1] as f64], [b[0] as f64, b[1] as f64])
+    })
+}
+
+fn winding(polygon: &[[f32; 2]]) -> f64 {
+    edges(polygon)
+        .map(|(a, b)| a[0] * b[1] - b[0] * a[1])
+        .sum::<f64>()
+        .signum()
+}
+
+fn polygon_contains(polygon: &[[f32; 2]], p: [f32; 2]) -> Option<bool> {
+    let w = winding(polygon);
+    let p = [p[0] as f64, p[1] as f64];
+    all(edges(polygon).map(|(a, b)| {
+        let cross = (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0]);
+        ge(cross * w, 0.0, 0.1)
+    }))
+}
+
+/// Separating axis test between the box and the polygon, on the axes of the box and the normals of the edges
+fn polygon_intersects_box(polygon: &[[f32; 2]], b: &Box2) -> Option<bool> {
+    let corners = [b.ll, [b.ur[0], b.ll[1]], b.ur, [b.ll[0], b.ur[1]]];
+    let mut axes = vec![[1.0, 0.0], [0.0, 1.0]];
+    axes.extend(edges(polygon).map(|(a, b)| {
+        let n = [a[1] - b[1], b[0] - a[0]];
+        let len = (n[0] * n[0] + n[1] * n[1]).sqrt();
+        [n[0] / len, n[1] / len]
+    }));
+
+    let project = |points: &[[f32; 2]], axis: [f64; 2]| {
+        points
+            .iter()
+            .map(|p| p[0] as f64 * axis[0] + p[1] as f64 * axis[1])
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), x| {
+                (lo.min(x), hi.max(x))
+            })
+    };
+    all(axes.into_iter().map(|axis| {
+        let (a_lo, a_hi) = project(polygon, axis);
+        let (b_lo, b_hi) = project(&corners, axis);
+        ge(0.0, (a_lo - b_hi).max(b_lo - a_hi), 1e-3)
+    }))
+}
+
+#[test]
+fn grid_query_polygon_matches_brute_force() {
+    let rng = fastrand::Rng::with_seed(1);
+    let mut n_hits = 0;
+    for cell_size in CELL_SIZES {
+        let g = random_grid(&rng, cell_size);
+        let points = points(&g);
+        for _ in 0..300 {
+            let polygon = random_polygon(&rng);
+            n_hits += assert_found(
+                g.query_polygon(&polygon).map(|(h, _)| h),
+                points
+                    .iter()
+                    .map(|&(h, p)| (h, polygon_contains(&polygon, p))),
+                (cell_size, &polygon),
+            );
+        }
+    }
+    assert!(n_hits > 10_000, "only {} hits", n_hits);
+}
+
+#[test]
+fn aabbgrid_query_polygon_matches_brute_force() {
+    let rng = fastrand::Rng::with_seed(2);
+    let mut n_hits = 0;
+    for cell_size in CELL_SIZES {
+        let g = random_aabbgrid(&rng, cell_size);
+        let boxes = boxes(&g);
+        for _ in 0..300 {
+            let polygon = random_polygon(&rng);
+            n_hits += assert_found(
+                g.query_polygon(&polygon).map(|(h, _, _)| h),
+                boxes
+                    .iter()
+                    .map(|(h, b)| (*h, polygon_intersects_box(&polygon, b))),
+                (cell_size, &polygon),
+            );
+        }
+    }
+    assert!(n_hits > 10_000, "only {} hits", n_hits);
+}
+
+#[test]
+fn query_annulus_matches_brute_force() {
+    let rng = fastrand::Rng::with_seed(3);
+    let mut n_hits = 0;
+    for cell_size in CELL_SIZES {
+        let g = random_grid(&rng, cell_size);
+        let points = points(&g);
+        for _ in 0..300 {
+            let center = random_point(&rng, rng.bool());
+            let min_radius = if rng.u32(0..4) == 0 {
+                0.0
+            } else {
+                random_radius(&rng)
+            };
+            // Sometimes smaller than the min radius, which is empty
+            let max_radius = (min_radius + random_radius(&rng) - 10.0).max(0.0);
+            let (min2, max2) = (
+                (min_radius * min_radius) as f64,
+                (max_radius * max_radius) as f64,
+            );
+
+            n_hits += assert_found(
+                g.query_annulus(center, min_radius, max_radius)
+                    .map(|(h, _)| h),
+                points.iter().map(|&(h, p)| {
+                    let d2 = dist2(center, p);
+                    (h, all([ge(d2, min2, 0.05), lt(d2, max2, 0.05)]))
+                }),
+                (cell_size, center, min_radius, max_radius),
+            );
+        }
+    }
+    assert!(n_hits > 10_000, "only {} hits", n_hits);
+}
+
+#[test]
+fn query_cone_matches_brute_force() {
+    use std::f32::consts::{FRAC_PI_2, PI};
+
+    let rng = fastrand::Rng::with_seed(4);
+    let mut n_hits = 0;
+    for cell_size in CELL_SIZES {
+        let g = random_grid(&rng, cell_size);
+        let points = points(&g);
+        for _ in 0..500 {
+            let center = random_point(&rng, rng.bool());
+            let dir = match rng.u32(0..4) {
+                0 => [0.0, 0.0],
+                1 => [[1.0, 0.0], [0.0, 1.0], [-1.0, 0.0], [0.0, -3.0]][rng.usize(..4)],
+                _ => [rng.f32() - 0.5, rng.f32() - 0.5],
+            };
+            // Wider than a half plane and than the whole circle too
+            let half_angle = match rng.u32(0..6) {
+                0 => FRAC_PI_2,
+                1 => PI,
+                2 => PI + rng.f32() * 3.0,
+                _ => rng.f32() * PI,
+            };
+            let radius = random_radius(&rng);
+
+            let len = ((dir[0] * dir[0] + dir[1] * dir[1]) as f64).sqrt();
+            let d = if len > 0.0 {
+                [dir[0] as f64 / len, dir[1] as f64 / len]
+            } else {
+                [1.0, 0.0]
+            };
+            let cos = (half_angle as f64).cos();
+
+            n_hits += assert_found(
+                g.query_cone(center, dir, half_angle, radius)
+                    .map(|(h, _)| h),
+                points.iter().map(|&(h, p)| {
+                    let d2 = dist2(center, p);
+                    let x = p[0] as f64 - center[0] as f64;
+                    let y = p[1] as f64 - center[1] as f64;
+                    let in_angle = if half_angle >= PI {
+                        Some(true)
+                    } else {
+                        ge(x * d[0] + y * d[1], d2.sqrt() * cos, 1e-3)
+                    };
+                    let in_radius = lt(d2, (radius * radius) as f64, 0.05);
+                    (h, all([in_radius, in_angle]))
+                }),
+                (cell_size, center, dir, half_angle, radius),
+            );
+        }
+    }
+    assert!(n_hits > 10_000, "only {} hits", n_hits);
+}
+
+#[test]
+fn query_around_sorted_matches_brute_force() {
+    let rng = fastrand::Rng::with_seed(5);
+    let mut n_hits = 0;
+    for cell_size in CELL_SIZES {
+        let g = random_grid(&rng, cell_size);
+        let points = points(&g);
+        for _ in 0..300 {
+            let center = random_point(&rng, rng.bool());
+            let radius = random_radius(&rng);
+            let radius2 = (radius * radius) as f64;
+
+            let sorted: Vec<_> = g.query_around_sorted(center, radius).collect();
+            let context = (cell_size, center, radius);
+            for w in sorted.windows(2) {
+                assert!(
+                    dist2(center, w[0].1) <= dist2(center, w[1].1) + 1e-3,
+                    "{:?} is after {:?} for {:?}",
+                    w[1],
+                    w[0],
+                    context
+                );
+            }
+
+            // Stopping early gives the nearest ones
+            let k = rng.usize(..=sorted.len());
+            let first: Vec<_> = g.query_around_sorted(center, radius).take(k).collect();
+            for (a, b) in first.iter().zip(&sorted) {
+                assert!((dist2(center, a.1) - dist2(center, b.1)).abs() < 1e-3);
+            }
+
+            n_hits += assert_found(
+                sorted.iter().map(|&(h, _)| h),
+                points
+                    .iter()
+                    .map(|&(h, p)| (h, lt(dist2(center, p), radius2, 0.05))),
+                context,
+            );
+        }
+    }
+    assert!(n_hits > 10_000, "only {} hits", n_hits);
+}
+
+/// Checks a count against the expected values, which may be ambiguous
+fn assert_count(count: usize, expected: impl IntoIterator<Item = Option<bool>>) -> usize {
+    let (mut lo, mut hi) = (0, 0);
+    for e in expected {
+        match e {
+            Some(true) => {
+                lo += 1;
+                hi += 1;
+            }
+            None => hi += 1,
+            Some(false) => {}
+        }
+    }
+    assert!(
+        lo <= count && count <= hi,
+        "{} should be in [{}, {}]",
+        count,
+        lo,
+        hi
+    );
+    lo
+}
+
+#[test]
+fn count_and_any_around_match_brute_force() {
+    let rng = fastrand::Rng::with_seed(6);
+    let mut n_hits = 0;
+    let mut n_empty = 0;
+    for cell_size in CELL_SIZES {
+        let g = random_grid(&rng, cell_size);
+        let points = points(&g);
+        for _ in 0..500 {
+            let center = random_point(&rng, rng.bool());
+            let radius = random_radius(&rng);
+            let radius2 = (radius * radius) as f64;
+            let expected: Vec<_> = points
+                .iter()
+                .map(|&(_, p)| lt(dist2(center, p), radius2, 0.05))
+                .collect();
+
+            let count = g.count_around(center, radius);
+            n_hits += assert_count(count, expected.iter().copied());
+            assert_eq!(count, g.query_around(center, radius).count());
+
+            let any = g.any_around(center, radius);
+            assert_eq!(any, count > 0, "{:?} {}", center, radius);
+            if !any {
+                n_empty += 1;
+            }
+        }
+    }
+    assert!(n_hits > 10_000, "only {} hits", n_hits);
+    assert!(n_empty > 10, "only {} empty queries", n_empty);
+}
+
+#[test]
+fn count_aabb_matches_brute_force() {
+    let rng = fastrand::Rng::with_seed(7);
+    let mut n_hits = 0;
+    for cell_size in CELL_SIZES {
+        let g = random_grid(&rng, cell_size);
+        let points = points(&g);
+        for _ in 0..500 {
+            // The corners can be given in any order
+            let snapped = rng.bool();
+            let a = random_point(&rng, snapped);
+            let b = random_point(&rng, snapped);
+            let b = [a[0] + (b[0] - a[0]) / 3.0, a[1] + (b[1] - a[1]) / 3.0];
+            let b = if snapped { [snap(b[0]), snap(b[1])] } else { b };
+            let (ll, ur) = (
+                [a[0].min(b[0]), a[1].min(b[1])],
+                [a[0].max(b[0]), a[1].max(b[1])],
+            );
+
+            let count = g.count_aabb(a, b);
+            n_hits += assert_count(
+                count,
+                points.iter().map(|&(_, p)| {
+                    Some(ll[0] <= p[0] && p[0] <= ur[0] && ll[1] <= p[1] && p[1] <= ur[1])
+                }),
+            );
+            assert_eq!(count, g.query_aabb(a, b).count());
+        }
+    }
+    assert!(n_hits > 10_000, "only {} hits", n_hits);
+}
+
+#[test]
+fn any_intersecting_matches_brute_force() {
+    let rng = fastrand::Rng::with_seed(8);
+    let mut n_any = 0;
+    for cell_size in CELL_SIZES {
+        let g = random_aabbgrid(&rng, cell_size);
+        let boxes = boxes(&g);
+        for _ in 0..1000 {
+            let snapped = rng.bool();
+            let ll = random_point(&rng, snapped);
+            let size = random_radius(&rng) / 16.0;
+            let size = if snapped { snap(size) } else { size };
+            let aabb = BoundingBox::new(ll, [ll[0] + size, ll[1] + size]);
+
+            let expected = boxes.iter().map(|(_, b)| {
+                all((0..2).map(|i| {
+                    let gap = (b.ll[i] - aabb.ur[i]).max(aabb.ll[i] - b.ur[i]);
+                    ge(0.0, gap as f64, 1e-3)
+                }))
+            });
+            let lo_hi = expected.fold((false, false), |(lo, hi), e| match e {
+                Some(x) => (lo || x, hi || x),
+                None => (lo, true),
+            });
+
+            let any = g.any_intersecting(aabb);
+            assert_eq!(any, g.query(aabb).next().is_some(), "{:?}", aabb);
+            assert!(lo_hi.0 <= any && any <= lo_hi.1, "{:?}", aabb);
+            if any {
+                n_any += 1;
+            }
+        }
+    }
+    assert!(n_any > 300 && n_any < 2700, "{} hits out of 3000", n_any);
+}
+
+#[test]
+fn query_mode_matches_brute_force() {
+    use flat_spatial::aabbgrid::QueryMode;
+
+    let contains = |outer: &Box2, inner: &Box2| {
+        outer.ll[0] <= inner.ll[0]
+            && outer.ll[1] <= inner.ll[1]
+            && inner.ur[0] <= outer.ur[0]
+            && inner.ur[1] <= outer.ur[1]
+    };
+
+    let rng = fastrand::Rng::with_seed(9);
+    let mut n_hits = [0; 3];
+    for cell_size in CELL_SIZES {
+        let g = random_aabbgrid(&rng, cell_size);
+        let boxes = boxes(&g);
+        for _ in 0..1000 {
+            let snapped = rng.bool();
+            let ll = random_point(&rng, snapped);
+            // Small queries are contained by objects, large ones contain them
+            let size = [random_radius(&rng), random_radius(&rng)];
+            let size = if rng.bool() {
+                [size[0] / 16.0, size[1] / 16.0]
+            } else {
+                size
+            };
+            let size = if snapped {
+                [snap(size[0]), snap(size[1])]
+            } else {
+                size
+            };
+            let aabb = BoundingBox::new(ll, [ll[0] + size[0], ll[1] + size[1]]);
+
+            for (i, mode) in [
+                QueryMode::Intersects,
+                QueryMode::ContainedBy,
+                QueryMode::Contains,
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                let found: Vec<_> = g.query_mode(aabb, mode).map(|(h, _, _)| h).collect();
+                let mut visited = vec![];
+                g.query_visitor_mode(aabb, mode, |h, _, _| visited.push(h));
+                assert_eq!(found, visited);
+
+                n_hits[i] += assert_found(
+                    found,
+                    boxes.iter().map(|(h, b)| {
+                        let expected = match mode {
+                            QueryMode::Intersects => all((0..2).map(|i| {
+                                let gap = (b.ll[i] - aabb.ur[i]).max(aabb.ll[i] - b.ur[i]);
+                                ge(0.0, gap as f64, 1e-3)
+                            })),
+                            QueryMode::ContainedBy => Some(contains(&aabb, b)),
+                            QueryMode::Contains => Some(contains(b, &aabb)),
+                        };
+                        (*h, expected)
+                    }),
+                    (cell_size, aabb, mode),
+                );
+            }
+        }
+    }
+    assert!(
+        n_hits.iter().all(|&n| n > 1000),
+        "hits for each mode: {:?}",
+        n_hits
+    );
+}
@@ -0,0 +1,196 @@
+//! The GJK narrow phase is compared with exact distances: every shape is a convex polygon
+//! (a single point for circles and a segment for capsules) inflated by a radius.
+
+use flat_spatial::shape::{Capsule, Circle, ConvexPolygon, Obb, Shape};
+
+type P = [f32; 2];
+
+fn sub(a: P, b: P) -> P {
+    [a[0] - b[0], a[1] - b[1]]
+}
+
+fn dot(a: P, b: P) -> f32 {
+    a[0] * b[0] + a[1] * b[1]
+}
+
+fn cross(a: P, b: P) -> f32 {
+    a[0] * b[1] - a[1] * b[0]
+}
+
+fn point_segment_distance(p: P, a: P, b: P) -> f32 {
+    let ab = sub(b, a);
+    let len2 = dot(ab, ab);
+    let t = if len2 > 0.0 {
+        (dot(sub(p, a), ab) / len2).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let d = sub(p, [a[0] + ab[0] * t, a[1] + ab[1] * t]);
+    dot(d, d).sqrt()
+}
+
+fn segments_cross(a: P, b: P, c: P, d: P) -> bool {
+    let d1 = cross(sub(b, a), sub(c, a));
+    let d2 = cross(sub(b, a), sub(d, a));
+    let d3 = cross(sub(d, c), sub(a, c));
+    let d4 = cross(sub(d, c), sub(b, c));
+    d1 * d2 < 0.0 && d3 * d4 < 0.0
+}
+
+/// Strictly inside a convex polygon of any winding with at least 3 vertices
+fn inside(p: P, poly: &[P]) -> bool {
+    if poly.len() < 3 {
+        return false;
+    }
+    let sides: Vec<f32> = (0..poly.len())
+        .map(|i| cross(sub(poly[(i + 1) % poly.len()], poly[i]), sub(p, poly[i])))
+        .collect();
+    sides.iter().all(|&s| s > 0.0) || sides.iter().all(|&s| s < 0.0)
+}
+
+fn edges(poly: &[P]) -> Vec<(P, P)> {
+    (0..poly.len())
+        .map(|i| (poly[i], poly[(i + 1) % poly.len()]))
+        .collect()
+}
+
+/// Distance between two convex polygons, None if they overlap
+fn polygon_distance(a: &[P], b: &[P]) -> Option<f32> {
+    if a.iter().any(|&p| inside(p, b)) || b.iter().any(|&p| inside(p, a)) {
+        return None;
+    }
+    let mut best = f32::INFINITY;
+    for &(p, q) in &edges(a) {
+        for &(r, s) in &edges(b) {
+            if segments_cross(p, q, r, s) {
+                return None;
+            }
+            best = best
+                .min(point_segment_distance(p, r, s))
+                .min(point_segment_distance(q, r, s))
+                .min(point_segment_distance(r, p, q))
+                .min(point_segment_distance(s, p, q));
+        }
+    }
+    Some(best)
+}
+
+/// A shape as a convex polygon inflated by a radius
+#[derive(Clone, Copy, Debug)]
+enum Any {
+    Circle(Circle<P>),
+    Capsule(Capsule<P>),
+    Obb(Obb<P>),
+    Polygon(ConvexPolygon<P>),
+}
+
+impl Any {
+    fn core(&self) -> (Vec<P>, f32) {
+        match self {
+            Any::Circle(x) => (vec![x.center], x.radius),
+            Any::Capsule(x) => (vec![x.a, x.b], x.radius),
+            Any::Obb(x) => (x.corners().to_vec(), 0.0),
+            Any::Polygon(x) => (x.points().to_vec(), 0.0),
+        }
+    }
+
+    fn intersects_shape(&self, other: &Any) -> bool {
+        fn with<S: Shape>(a: &S, b: &Any) -> bool {
+            match b {
+                Any::Circle(x) => a.intersects_shape(x),
+                Any::Capsule(x) => a.intersects_shape(x),
+                Any::Obb(x) => a.intersects_shape(x),
+                Any::Polygon(x) => a.intersects_shape(x),
+            }
+        }
+        match self {
+            Any::Circle(x) => with(x, other),
+            Any::Capsule(x) => with(x, other),
+            Any::Obb(x) => with(x, other),
+            Any::Polygon(x) => with(x, other),
+        }
+    }
+}
+
+fn random_shape(rng: &fastrand::Rng, kind: usize) -> Any {
+    let point = || [rng.f32() * 40.0 - 20.0, rng.f32() * 40.0 - 20.0];
+    let center = point();
+    match kind {
+        0 => Any::Circle(Circle::new(center, rng.f32() * 6.0)),
+        1 => Any::Capsule(Capsule::new(center, point(), rng.f32() * 4.0)),
+        2 => Any::Obb(Obb::new(
+            center,
+            [rng.f32() * 8.0, rng.f32() * 8.0],
+            rng.f32() * 6.3,
+        )),
+        _ => {
+            // Points on a circle are convex, in either winding
+            let n = rng.usize(3..=8);
+            let mut angles: Vec<f32> = (0..n).map(|_| rng.f32() * 6.3).collect();
+            angles.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            if rng.bool() {
+                angles.reverse();
+            }
+            let (rx, ry) = (1.0 + rng.f32() * 8.0, 1.0 + rng.f32() * 8.0);
+            let points: Vec<P> = angles
+                .iter()
+                .map(|a| [center[0] + rx * a.cos(), center[1] + ry * a.sin()])
+                .collect();
+            Any::Polygon(ConvexPolygon::new(&points))
+        }
+    }
+}
+
+#[test]
+fn gjk_matches_exact_distance() {
+    let rng = fastrand::Rng::with_seed(1);
+    let names = ["circle", "capsule", "obb", "polygon"];
+    for ka in 0..4 {
+        for kb in 0..4 {
+            let (mut hits, mut misses) = (0, 0);
+            for _ in 0..3000 {
+                let a = random_shape(&rng, ka);
+                let b = random_shape(&rng, kb);
+                let ((pa, ra), (pb, rb)) = (a.core(), b.core());
+                let expected = match polygon_distance(&pa, &pb) {
+                    // Too close to touching for f32
+                    Some(d) if (d - ra - rb).abs() < 1e-3 => continue,
+                    Some(d) => d < ra + rb,
+                    None => true,
+                };
+                assert_eq!(
+                    a.intersects_shape(&b),
+                    expected,
+                    "{} {:?} and {} {:?}",
+                    names[ka],
+                    a,
+                    names[kb],
+                    b
+                );
+                if expected {
+                    hits += 1;
+                } else {
+                    misses += 1;
+                }
+            }
+            assert!(hits > 100 && misses > 100, "{} {}", names[ka], names[kb]);
+        }
+    }
+}
+
+#[test]
+fn near_misses_are_not_hits() {
+    let rng = fastrand::Rng::with_seed(2);
+    for _ in 0..10_000 {
+        let (r1, r2) = (0.1 + rng.f32() * 20.0, 0.1 + rng.f32() * 20.0);
+        let angle = rng.f32() * 6.3;
+        let d = r1 + r2 + 1e-2;
+        let a = Circle::new([0.0, 0.0], r1);
+        let b = Circle::new([d * angle.cos(), d * angle.sin()], r2);
+        assert!(!a.intersects_shape(&b));
+
+        let c = Capsule::new([-d, 0.0], [d, 0.0], r1);
+        let o = Obb::new([0.0, r1 + 1e-2 + r2], [r2 * 2.0, r2], 0.0);
+        assert!(!c.intersects_shape(&o));
+    }
+}
@@ -0,0 +1,163 @@
+use flat_spatial::grid::GridHandle;
+use flat_spatial::Grid;
+use std::time::Instant;
+
+type Cells = Vec<((i32, i32), Vec<(GridHandle, [f32; 2])>)>;
+
+fn pos(rng: &fastrand::Rng) -> [f32; 2] {
+    [rng.f32() * 200.0 - 100.0, rng.f32() * 200.0 - 100.0]
+}
+
+fn cells(g: &Grid<u32, [f32; 2]>) -> Cells {
+    let mut cells: Vec<_> = g
+        .storage()
+        .cells
+        .iter()
+        .map(|(&id, cell)| (id, cell.objs.clone()))
+        .collect();
+    cells.sort_by_key(|&(id, _)| id);
+    cells
+}
+
+fn assert_same(g: &Grid<u32, [f32; 2]>, expected: &Grid<u32, [f32; 2]>) {
+    assert_eq!(cells(g), cells(expected));
+    let handles: Vec<_> = g.handles().collect();
+    assert_eq!(handles, expected.handles().collect::<Vec<_>>());
+    for h in handles {
+        assert_eq!(g.get(h), expected.get(h));
+    }
+    assert_eq!(g.validate(), Ok(()));
+    // Same handle allocation state
+    assert_eq!(
+        g.clone().insert([0.0, 0.0], 0),
+        expected.clone().insert([0.0, 0.0], 0)
+    );
+}
+
+fn random_change(rng: &fastrand::Rng, g: &mut Grid<u32, [f32; 2]>) {
+    let handles: Vec<_> = g.handles().collect();
+    let h = if handles.is_empty() {
+        None
+    } else {
+        Some(handles[rng.usize(..handles.len())])
+    };
+
+    match (rng.u32(0..100), h) {
+        (0..=14, _) | (_, None) => {
+            g.insert(pos(rng), rng.u32(..));
+        }
+        (15..=24, Some(h)) => {
+            g.remove(h);
+        }
+        (25..=29, Some(h)) => {
+            g.remove_maintain(h);
+        }
+        (30..=59, Some(h)) => g.set_position(h, pos(rng)),
+        (60..=74, Some(h)) => g.set_position_maintain(h, pos(rng)),
+        (75..=82, Some(h)) => *g.get_mut(h).unwrap().1 = rng.u32(..),
+        (83..=84, _) => {
+            g.extend((0..rng.usize(1..5)).map(|_| (pos(rng), rng.u32(..))));
+        }
+        (85..=90, _) => g.maintain(),
+        #[cfg(feature = "rayon")]
+        (91..=94, _) => g.par_maintain(),
+        (91..=97, _) => g.maintain_deterministic(),
+        (98, _) => g.rebuild(rng.i32(5..20)),
+        (_, _) => {
+            let _ = g.clear();
+        }
+    }
+}
+
+#[test]
+fn restore_matches_clones() {
+    let rng = fastrand::Rng::with_seed(1);
+    let mut g: Grid<u32, [f32; 2]> = Grid::new(10);
+    for _ in 0..200 {
+        g.insert(pos(&rng), 0);
+    }
+
+    let mut snapshots = vec![];
+    for _ in 0..300 {
+        for _ in 0..rng.usize(0..30) {
+            random_change(&rng, &mut g);
+        }
+
+        match rng.u32(0..10) {
+            0..=4 => snapshots.push((g.snapshot(), g.clone())),
+            5..=7 if !snapshots.is_empty() => {
+                let k = rng.usize(..snapshots.len());
+                snapshots.truncate(k + 1);
+                let (snap, expected) = &snapshots[k];
+                assert!(g.restore(*snap));
+                assert_same(&g, expected);
+            }
+            8 if !snapshots.is_empty() => {
+                let k = rng.usize(..snapshots.len());
+                g.forget_snapshots_before(snapshots[k].0);
+                for (snap, _) in snapshots.drain(..k) {
+                    assert!(!g.restore(snap));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn clone_has_no_snapshots() {
+    let mut g: Grid<u32, [f32; 2]> = Grid::new(10);
+    let snap = g.snapshot();
+    let a = g.insert([1.0, 1.0], 1);
+
+    let mut c = g.clone();
+    assert!(!c.restore(snap));
+    assert_eq!(c.get(a), Some(([1.0, 1.0], &1)));
+    assert!(g.restore(snap));
+    assert_eq!(g.get(a), None);
+}
+
+#[test]
+fn insertions_and_removals_are_not_copied() {
+    let rng = fastrand::Rng::with_seed(2);
+    let mut g: Grid<u32, [f32; 2]> = Grid::new(10);
+    for _ in 0..200_000 {
+        g.insert(pos(&rng), 0);
+    }
+
+    let start = Instant::now();
+    let _ = g.clone();
+    let clone_time = start.elapsed();
+
+    // A rolling window of snapshots, with an insertion and a removal every frame
+    let mut snapshots = std::collections::VecDeque::new();
+    let start = Instant::now();
+    for _ in 0..1000 {
+        snapshots.push_back(g.snapshot());
+        if snapshots.len() > 8 {
+            snapshots.pop_front();
+            g.forget_snapshots_before(snapshots[0]);
+        }
+        let h = g.insert(pos(&rng), 1);
+        g.remove_maintain(h);
+        g.insert(pos(&rng), 2);
+    }
+    let frames_time = start.elapsed();
+
+    // Only the first snapshot copies the objects, copying them every frame would take about 1000 clones
+    assert!(
+        frames_time < clone_time * 50,
+        "1000 frames took {:?}, a clone takes {:?}",
+        frames_time,
+        clone_time
+    );
+
+    // Each of the 8 frames since the oldest snapshot added one object
+    let expected = g.clone();
+    let snap = snapshots[0];
+    for _ in 0..10 {
+        g.insert(pos(&rng), 3);
+    }
+    assert!(g.restore(snap));
+    assert_eq!(g.len(), expected.len() - 8);
+}
@@ -0,0 +1,231 @@
+use flat_spatial::aabbgrid::AABBGridHandle;
+use flat_spatial::shape::BoundingBox;
+use flat_spatial::AABBGrid;
+
+type Box2 = BoundingBox<[f32; 2]>;
+
+/// A box in [-150, 150], snapped to the cell boundaries half of the time
+fn random_box(rng: &fastrand::Rng, max_size: f32) -> Box2 {
+    let snap = rng.bool();
+    let c = |v: f32| if snap { (v / 5.0).round() * 5.0 } else { v };
+    let ll = [c(rng.f32() * 300.0 - 150.0), c(rng.f32() * 300.0 - 150.0)];
+    let size = [c(rng.f32() * max_size), c(rng.f32() * max_size)];
+    BoundingBox::new(ll, [ll[0] + size[0], ll[1] + size[1]])
+}
+
+fn random_velocity(rng: &fastrand::Rng) -> [f32; 2] {
+    let c = |max: f32| match rng.u32(0..4) {
+        0 => 0.0,
+        1 => ((rng.f32() * 2.0 - 1.0) * max).round(),
+        _ => (rng.f32() * 2.0 - 1.0) * max,
+    };
+    let max = [10.0, 100.0, 400.0][rng.usize(..3)];
+    [c(max), c(max)]
+}
+
+fn boxes(g: &AABBGrid<(), Box2>) -> Vec<(AABBGridHandle, Box2)> {
+    g.handles().map(|h| (h, g.get(h).unwrap().aabb)).collect()
+}
+
+/// Time interval during which the boxes overlap on each axis, borders included, computed in f64
+fn slab(moving: &Box2, v: [f32; 2], other: &Box2) -> Option<(f64, f64, usize)> {
+    let mut enter = f64::NEG_INFINITY;
+    let mut exit = f64::INFINITY;
+    let mut axis = 0;
+    for i in 0..2 {
+        let (ll, ur) = (moving.ll[i] as f64, moving.ur[i] as f64);
+        let (oll, our) = (other.ll[i] as f64, other.ur[i] as f64);
+        let v = v[i] as f64;
+        if v == 0.0 {
+            if ur < oll || our < ll {
+                return None;
+            }
+            continue;
+        }
+        let (a, b) = ((oll - ur) / v, (our - ll) / v);
+        let (lo, hi) = (a.min(b), a.max(b));
+        if lo > enter {
+            enter = lo;
+            axis = i;
+        }
+        exit = exit.min(hi);
+    }
+    Some((enter, exit, axis))
+}
+
+/// Checks the hits of a sweep against the slab test on every object, skipping the objects that
+/// are within rounding errors of being touched or not
+fn check_sweep(g: &AABBGrid<(), Box2>, moving: Box2, v: [f32; 2]) -> usize {
+    const EPS: f64 = 1e-4;
+
+    let hits = g.sweep(moving, v);
+    for w in hits.windows(2) {
+        assert!(w[0].toi <= w[1].toi, "hits are not sorted: {:?}", hits);
+    }
+    let mut found: Vec<AABBGridHandle> = hits.iter().map(|h| h.handle).collect();
+    found.sort();
+    found.dedup();
+    assert_eq!(found.len(), hits.len(), "duplicated hits: {:?}", hits);
+
+    let mut n_hits = 0;
+    for (h, ref other) in boxes(g) {
+        let hit = hits.iter().find(|x| x.handle == h);
+
+        let (enter, exit, axis) = match slab(&moving, v, other) {
+            Some(x) => x,
+            None => {
+                // Disjoint on an axis without velocity: only touching boxes are ambiguous
+                let gap = (0..2)
+                    .filter(|&i| v[i] == 0.0)
+                    .map(|i| {
+                        (other.ll[i] - moving.ur[i])
+                            .max(moving.ll[i] - other.ur[i])
+                            .abs()
+                    })
+                    .fold(f32::INFINITY, f32::min);
+                if gap > 1e-3 {
+                    assert!(hit.is_none(), "{:?} {:?} {:?} {:?}", moving, v, other, hit);
+                }
+                continue;
+            }
+        };
+
+        let expected = enter <= exit && enter <= 1.0 && exit >= 0.0;
+        let ambiguous = (enter - exit).abs() < EPS
+            || (enter - 1.0).abs() < EPS
+            || exit.abs() < EPS
+            || enter.abs() < EPS;
+        if ambiguous {
+            continue;
+        }
+        let hit = match (expected, hit) {
+            (false, None) => continue,
+            (true, Some(hit)) => hit,
+            _ => panic!(
+                "{:?} moving by {:?} against {:?}: expected hit {}, got {:?}",
+                moving, v, other, expected, hit
+            ),
+        };
+        n_hits += 1;
+
+        let toi = enter.max(0.0);
+        assert!(
+            (hit.toi as f64 - toi).abs() < EPS,
+            "{:?} moving by {:?} against {:?}: toi {} instead of {}",
+            moving,
+            v,
+            other,
+            hit.toi,
+            toi
+        );
+        if enter < 0.0 {
+            assert_eq!(hit.normal, [0.0, 0.0]);
+        } else {
+            let other_axis = slab(
+                &moving,
+                if axis == 0 { [0.0, v[1]] } else { [v[0], 0.0] },
+                other,
+            );
+            // The normal is only well defined if the boxes do not touch by a corner
+            let corner = match other_axis {
+                Some((e, _, _)) => (e - enter).abs() < EPS,
+                None => false,
+            };
+            if !corner {
+                let mut normal = [0.0; 2];
+                normal[axis] = -v[axis].signum();
+                assert_eq!(hit.normal, normal, "{:?} {:?} {:?}", moving, v, other);
+            }
+        }
+    }
+    n_hits
+}
+
+#[test]
+fn sweep_matches_slab_test() {
+    let rng = fastrand::Rng::with_seed(1);
+    let mut n_hits = 0;
+    for cell_size in [3, 10, 64] {
+        let mut g: AABBGrid<(), Box2> = AABBGrid::new(cell_size);
+        for _ in 0..300 {
+            g.insert(random_box(&rng, 30.0), ());
+        }
+        for _ in 0..300 {
+            g.insert(random_box(&rng, 2.0), ());
+        }
+
+        for _ in 0..1000 {
+            n_hits += check_sweep(&g, random_box(&rng, 20.0), random_velocity(&rng));
+        }
+    }
+    assert!(n_hits > 1000, "only {} hits", n_hits);
+}
+
+#[test]
+fn zero_velocity_is_static_overlap() {
+    let rng = fastrand::Rng::with_seed(2);
+    let mut g: AABBGrid<(), Box2> = AABBGrid::new(10);
+    for _ in 0..500 {
+        g.insert(random_box(&rng, 30.0), ());
+    }
+
+    for _ in 0..500 {
+        let moving = random_box(&rng, 20.0);
+        let hits = g.sweep(moving, [0.0, 0.0]);
+        assert!(hits.iter().all(|h| h.toi == 0.0 && h.normal == [0.0, 0.0]));
+
+        let mut found: Vec<_> = hits.iter().map(|h| h.handle).collect();
+        found.sort();
+        let mut expected: Vec<_> = boxes(&g)
+            .into_iter()
+            .filter(|(_, b)| {
+                b.ll[0] <= moving.ur[0]
+                    && moving.ll[0] <= b.ur[0]
+                    && b.ll[1] <= moving.ur[1]
+                    && moving.ll[1] <= b.ur[1]
+            })
+            .map(|(h, _)| h)
+            .collect();
+        expected.sort();
+        assert_eq!(found, expected, "{:?}", moving);
+    }
+}
+
+#[test]
+fn sweep_across_negative_cell_boundaries() {
+    let mut g: AABBGrid<(), Box2> = AABBGrid::new(10);
+    // Thin walls lying exactly on the boundaries of the cells, at negative coordinates
+    let walls: Vec<_> = (1..=5)
+        .map(|i| {
+            let x = -10.0 * i as f32;
+            g.insert(BoundingBox::new([x, -45.0], [x, -15.0]), ())
+        })
+        .collect();
+    // Touched by a corner at the end of the sweep
+    let corner = g.insert(BoundingBox::new([-65.0, -35.0], [-62.0, -30.0]), ());
+
+    let moving = BoundingBox::new([-5.0, -20.0], [-4.0, -19.0]);
+    let hits = g.sweep(moving, [-57.0, -10.0]);
+
+    let handles: Vec<_> = hits.iter().map(|h| h.handle).collect();
+    let mut expected = walls.clone();
+    expected.push(corner);
+    assert_eq!(handles, expected);
+
+    for (i, hit) in hits[..5].iter().enumerate() {
+        let toi = (10.0 * (i + 1) as f32 - 5.0) / 57.0;
+        assert!((hit.toi - toi).abs() < 1e-6, "{:?}", hit);
+        assert_eq!(hit.normal, [1.0, 0.0]);
+    }
+    assert_eq!(hits[5].toi, 1.0);
+
+    // Moving the other way only starts on the first wall
+    let hits = g.sweep(moving, [57.0, 10.0]);
+    assert!(hits.is_empty(), "{:?}", hits);
+    let hits = g.sweep(
+        BoundingBox::new([-11.0, -20.0], [-10.0, -19.0]),
+        [57.0, 10.0],
+    );
+    assert_eq!(hits.len(), 1);
+    assert_eq!((hits[0].handle, hits[0].toi), (walls[0], 0.0));
+}
@@ -0,0 +1,474 @@
+#![cfg(feature = "serde")]
+
+//! Grids are tampered with through their serialized form, and deserializing them must report
+//! exactly the broken invariants.
+
+use flat_spatial::aabbgrid::AABBGridHandle;
+use flat_spatial::grid::GridHandle;
+use flat_spatial::shape::BoundingBox;
+use flat_spatial::validate::{CorruptedGrid, ValidationError, MAX_ERRORS};
+use flat_spatial::{AABBGrid, FrozenGrid, Grid};
+use serde_cbor::Value;
+use std::fmt::Debug;
+
+type PGrid = Grid<u32, [f32; 2]>;
+type BGrid = AABBGrid<u32, BoundingBox<[f32; 2]>>;
+type FGrid = FrozenGrid<u32, [f32; 2]>;
+
+fn field<'a>(v: &'a mut Value, name: &str) -> &'a mut Value {
+    match v {
+        Value::Map(m) => m.get_mut(&Value::Text(name.to_string())).unwrap(),
+        _ => panic!("not a map"),
+    }
+}
+
+fn cell(v: &mut Value, (x, y): (i32, i32)) -> &mut Value {
+    let key = Value::Array(vec![Value::Integer(x as i128), Value::Integer(y as i128)]);
+    match field(field(v, "storage"), "cells") {
+        Value::Map(m) => m.get_mut(&key).unwrap(),
+        _ => panic!("not a map"),
+    }
+}
+
+fn cell_objs(v: &mut Value, id: (i32, i32)) -> &mut Vec<Value> {
+    match field(cell(v, id), "objs") {
+        Value::Array(objs) => objs,
+        _ => panic!("not an array"),
+    }
+}
+
+/// The stored object of the given handle
+fn object(v: &mut Value, handle: impl serde::Serialize) -> &mut Value {
+    let mut handle = serde_cbor::value::to_value(handle).unwrap();
+    let idx = match field(&mut handle, "idx") {
+        Value::Integer(idx) => *idx as usize,
+        _ => panic!("not an integer"),
+    };
+    match field(v, "objects") {
+        Value::Array(objects) => match &mut objects[1] {
+            Value::Array(slots) => field(&mut slots[idx], "t"),
+            _ => panic!("not an array"),
+        },
+        _ => panic!("not an array"),
+    }
+}
+
+fn point(x: f32, y: f32) -> Value {
+    Value::Array(vec![Value::Float(x as f64), Value::Float(y as f64)])
+}
+
+fn assert_corrupted<T, H: Debug>(
+    res: Result<T, serde_cbor::Error>,
+    expected: Vec<ValidationError<H>>,
+) {
+    match res {
+        Ok(_) => panic!("corrupted grid was accepted"),
+        Err(e) => assert_eq!(e.to_string(), CorruptedGrid::Invalid(expected).to_string()),
+    }
+}
+
+/// a at (3, 3) in cell (0, 0), b at (13, 3) in cell (1, 0)
+fn grid() -> (PGrid, GridHandle, GridHandle) {
+    let mut g = PGrid::new(10);
+    let a = g.insert([3.0, 3.0], 1);
+    let b = g.insert([13.0, 3.0], 2);
+    (g, a, b)
+}
+
+fn tamper_grid(g: &PGrid, f: impl FnOnce(&mut Value)) -> Result<PGrid, serde_cbor::Error> {
+    let mut v = serde_cbor::value::to_value(g).unwrap();
+    f(&mut v);
+    serde_cbor::value::from_value(v)
+}
+
+/// a covers cell (0, 0), b covers cells (0, 0) and (1, 0)
+fn aabbgrid() -> (BGrid, AABBGridHandle, AABBGridHandle) {
+    let mut g = BGrid::new(10);
+    let a = g.insert(BoundingBox::new([1.0, 1.0], [2.0, 2.0]), 1);
+    let b = g.insert(BoundingBox::new([8.0, 1.0], [12.0, 2.0]), 2);
+    (g, a, b)
+}
+
+fn tamper_aabbgrid(g: &BGrid, f: impl FnOnce(&mut Value)) -> Result<BGrid, serde_cbor::Error> {
+    let mut v = serde_cbor::value::to_value(g).unwrap();
+    f(&mut v);
+    serde_cbor::value::from_value(v)
+}
+
+/// Keys are sorted as [cell (0, 0), cell (1, 0)] and entries as [a, b]
+fn tamper_frozen(g: &FGrid, f: impl FnOnce(&mut Value)) -> Result<FGrid, serde_cbor::Error> {
+    let mut v = serde_cbor::value::to_value(g).unwrap();
+    f(&mut v);
+    serde_cbor::value::from_value(v)
+}
+
+fn array(v: &mut Value) -> &mut Vec<Value> {
+    match v {
+        Value::Array(a) => a,
+        _ => panic!("not an array"),
+    }
+}
+
+fn assert_frozen_corrupted(
+    res: Result<FGrid, serde_cbor::Error>,
+    expected: CorruptedGrid<GridHandle>,
+) {
+    match res {
+        Ok(_) => panic!("corrupted grid was accepted"),
+        Err(e) => assert_eq!(e.to_string(), expected.to_string()),
+    }
+}
+
+#[test]
+fn untouched_grids_are_valid() {
+    let (g, _, _) = grid();
+    assert!(tamper_grid(&g, |_| {}).is_ok());
+    let (g, _, _) = aabbgrid();
+    assert!(tamper_aabbgrid(&g, |_| {}).is_ok());
+    let (g, _, _) = grid();
+    assert!(tamper_frozen(&g.freeze(), |_| {}).is_ok());
+}
+
+#[test]
+fn grid_dead_handle() {
+    let (mut g, _, _) = grid();
+    let c = g.insert([4.0, 4.0], 3);
+    let stale_storage = field(&mut serde_cbor::value::to_value(&g).unwrap(), "storage").clone();
+    g.remove(c);
+    g.maintain();
+
+    let res = tamper_grid(&g, |v| *field(v, "storage") = stale_storage);
+    assert_corrupted(
+        res,
+        vec![ValidationError::DeadHandle {
+            cell: (0, 0),
+            handle: c,
+        }],
+    );
+}
+
+#[test]
+fn grid_wrong_cell() {
+    let (g, a, _) = grid();
+    let res = tamper_grid(&g, |v| {
+        let entry = cell_objs(v, (0, 0)).remove(0);
+        cell_objs(v, (1, 0)).push(entry);
+    });
+    assert_corrupted(
+        res,
+        vec![
+            ValidationError::WrongCell {
+                cell: (1, 0),
+                handle: a,
+            },
+            ValidationError::MissingFromCell {
+                cell: (0, 0),
+                handle: a,
+            },
+        ],
+    );
+}
+
+#[test]
+fn grid_missing_from_cell() {
+    let (g, a, _) = grid();
+    let res = tamper_grid(&g, |v| cell_objs(v, (0, 0)).clear());
+    assert_corrupted(
+        res,
+        vec![ValidationError::MissingFromCell {
+            cell: (0, 0),
+            handle: a,
+        }],
+    );
+}
+
+#[test]
+fn grid_errors_are_capped() {
+    let mut g = PGrid::new(10);
+    for i in 0..MAX_ERRORS * 2 {
+        g.insert([3.0, 3.0], i as u32);
+    }
+    let res = tamper_grid(&g, |v| cell_objs(v, (0, 0)).clear());
+    let msg = res.err().expect("corrupted grid was accepted").to_string();
+    assert!(msg.starts_with(&format!(
+        "corrupted grid with {} broken invariants",
+        MAX_ERRORS
+    )));
+}
+
+#[test]
+fn grid_duplicate() {
+    let (g, a, _) = grid();
+    let res = tamper_grid(&g, |v| {
+        let objs = cell_objs(v, (0, 0));
+        objs.push(objs[0].clone());
+    });
+    assert_corrupted(
+        res,
+        vec![ValidationError::Duplicate {
+            cell: (0, 0),
+            handle: a,
+        }],
+    );
+}
+
+#[test]
+fn grid_position_mismatch() {
+    let (g, a, _) = grid();
+    let res = tamper_grid(&g, |v| match &mut cell_objs(v, (0, 0))[0] {
+        Value::Array(entry) => entry[1] = point(4.0, 4.0),
+        _ => panic!("not an array"),
+    });
+    assert_corrupted(
+        res,
+        vec![ValidationError::PositionMismatch {
+            cell: (0, 0),
+            handle: a,
+        }],
+    );
+}
+
+#[test]
+fn grid_not_dirty() {
+    let (mut g, _, b) = grid();
+    g.set_position(b, [14.0, 3.0]);
+    let res = tamper_grid(&g, |v| {
+        *field(cell(v, (1, 0)), "dirty") = Value::Bool(false)
+    });
+    assert_corrupted(
+        res,
+        vec![ValidationError::NotDirty {
+            cell: (1, 0),
+            handle: b,
+        }],
+    );
+}
+
+#[test]
+fn grid_invalid_state() {
+    let (g, a, _) = grid();
+    let res = tamper_grid(&g, |v| {
+        // A move that stays in cell (0, 0) to a position of cell (1, 0)
+        let mut state = std::collections::BTreeMap::new();
+        state.insert(Value::Text("NewPos".to_string()), point(13.0, 3.0));
+        *field(object(v, a), "state") = Value::Map(state);
+        *field(cell(v, (0, 0)), "dirty") = Value::Bool(true);
+    });
+    assert_corrupted(res, vec![ValidationError::InvalidState { handle: a }]);
+}
+
+#[test]
+fn aabbgrid_dead_handle() {
+    let (mut g, _, _) = aabbgrid();
+    let c = g.insert(BoundingBox::new([4.0, 4.0], [5.0, 5.0]), 3);
+    let stale_storage = field(&mut serde_cbor::value::to_value(&g).unwrap(), "storage").clone();
+    g.remove(c);
+    g.maintain();
+
+    let res = tamper_aabbgrid(&g, |v| *field(v, "storage") = stale_storage);
+    assert_corrupted(
+        res,
+        vec![ValidationError::DeadHandle {
+            cell: (0, 0),
+            handle: c,
+        }],
+    );
+}
+
+#[test]
+fn aabbgrid_wrong_cell() {
+    let (g, a, _) = aabbgrid();
+    let res = tamper_aabbgrid(&g, |v| {
+        let entry = cell_objs(v, (0, 0))[0].clone();
+        cell_objs(v, (1, 0)).push(entry);
+    });
+    assert_corrupted(
+        res,
+        vec![ValidationError::WrongCell {
+            cell: (1, 0),
+            handle: a,
+        }],
+    );
+}
+
+#[test]
+fn aabbgrid_missing_from_cell() {
+    let (g, _, b) = aabbgrid();
+    let res = tamper_aabbgrid(&g, |v| cell_objs(v, (1, 0)).clear());
+    assert_corrupted(
+        res,
+        vec![ValidationError::MissingFromCell {
+            cell: (1, 0),
+            handle: b,
+        }],
+    );
+}
+
+#[test]
+fn aabbgrid_huge_aabb_is_reported_once() {
+    let (g, _, b) = aabbgrid();
+    let res = tamper_aabbgrid(&g, |v| {
+        let aabb = field(object(v, b), "aabb");
+        *field(aabb, "ll") = point(-1e9, -1e9);
+        *field(aabb, "ur") = point(1e9, 1e9);
+    });
+    assert_corrupted(
+        res,
+        vec![ValidationError::MissingFromCell {
+            cell: (-100_000_001, -100_000_001),
+            handle: b,
+        }],
+    );
+}
+
+#[test]
+fn aabbgrid_duplicate() {
+    let (g, _, b) = aabbgrid();
+    let res = tamper_aabbgrid(&g, |v| {
+        let objs = cell_objs(v, (1, 0));
+        objs.push(objs[0].clone());
+    });
+    assert_corrupted(
+        res,
+        vec![ValidationError::Duplicate {
+            cell: (1, 0),
+            handle: b,
+        }],
+    );
+}
+
+#[test]
+fn aabbgrid_wrong_single_cell_flag() {
+    let (g, _, b) = aabbgrid();
+    let res = tamper_aabbgrid(&g, |v| match &mut cell_objs(v, (1, 0))[0] {
+        Value::Array(entry) => entry[1] = Value::Bool(true),
+        _ => panic!("not an array"),
+    });
+    assert_corrupted(
+        res,
+        vec![ValidationError::WrongSingleCellFlag {
+            cell: (1, 0),
+            handle: b,
+        }],
+    );
+}
+
+#[test]
+fn aabbgrid_not_dirty() {
+    let (mut g, a, _) = aabbgrid();
+    g.set_aabb_lazy(a, BoundingBox::new([1.0, 1.0], [3.0, 3.0]));
+    let res = tamper_aabbgrid(&g, |v| {
+        *field(cell(v, (0, 0)), "dirty") = Value::Bool(false)
+    });
+    assert_corrupted(
+        res,
+        vec![ValidationError::NotDirty {
+            cell: (0, 0),
+            handle: a,
+        }],
+    );
+}
+
+#[test]
+fn invalid_cell_size() {
+    for &cell_size in &[0, -10] {
+        let (g, _, _) = grid();
+        let res = tamper_grid(&g, |v| {
+            *field(field(v, "storage"), "cell_size") = Value::Integer(cell_size as i128)
+        });
+        let expected = CorruptedGrid::<GridHandle>::InvalidCellSize(cell_size);
+        assert_eq!(res.err().unwrap().to_string(), expected.to_string());
+
+        let (g, _, _) = aabbgrid();
+        let res = tamper_aabbgrid(&g, |v| {
+            *field(field(v, "storage"), "cell_size") = Value::Integer(cell_size as i128)
+        });
+        let expected = CorruptedGrid::<AABBGridHandle>::InvalidCellSize(cell_size);
+        assert_eq!(res.err().unwrap().to_string(), expected.to_string());
+    }
+}
+
+#[test]
+fn frozen_unsorted_keys() {
+    let (g, _, _) = grid();
+    let res = tamper_frozen(&g.freeze(), |v| array(field(v, "keys")).swap(0, 1));
+    assert_frozen_corrupted(res, CorruptedGrid::InvalidLayout);
+}
+
+#[test]
+fn frozen_offsets_out_of_bounds() {
+    let (g, _, _) = grid();
+    let res = tamper_frozen(&g.freeze(), |v| {
+        array(field(v, "offsets"))[2] = Value::Integer(3)
+    });
+    assert_frozen_corrupted(res, CorruptedGrid::InvalidLayout);
+}
+
+#[test]
+fn frozen_dead_handle() {
+    let (g, _, b) = grid();
+    let mut dead = serde_cbor::value::to_value(b).unwrap();
+    *field(&mut dead, "version") = Value::Integer(3);
+    let res = tamper_frozen(&g.freeze(), |v| match &mut array(field(v, "entries"))[1] {
+        Value::Array(entry) => entry[0] = dead.clone(),
+        _ => panic!("not an array"),
+    });
+    assert_frozen_corrupted(
+        res,
+        CorruptedGrid::Invalid(vec![
+            ValidationError::DeadHandle {
+                cell: (1, 0),
+                handle: serde_cbor::value::from_value(dead).unwrap(),
+            },
+            ValidationError::MissingFromCell {
+                cell: (1, 0),
+                handle: b,
+            },
+        ]),
+    );
+}
+
+#[test]
+fn frozen_wrong_cell() {
+    let (g, _, b) = grid();
+    // b is moved to the end of the first cell
+    let res = tamper_frozen(&g.freeze(), |v| {
+        array(field(v, "offsets"))[1] = Value::Integer(2)
+    });
+    assert_frozen_corrupted(
+        res,
+        CorruptedGrid::Invalid(vec![ValidationError::WrongCell {
+            cell: (0, 0),
+            handle: b,
+        }]),
+    );
+}
+
+#[test]
+fn frozen_duplicate() {
+    let (g, a, b) = grid();
+    let res = tamper_frozen(&g.freeze(), |v| {
+        let entries = array(field(v, "entries"));
+        entries[1] = entries[0].clone();
+    });
+    assert_frozen_corrupted(
+        res,
+        CorruptedGrid::Invalid(vec![
+            ValidationError::Duplicate {
+                cell: (1, 0),
+                handle: a,
+            },
+            ValidationError::MissingFromCell {
+                cell: (1, 0),
+                handle: b,
+            },
+        ]),
+    );
+}
+
+#[test]
+fn frozen_invalid_cell_size() {
+    let (g, _, _) = grid();
+    let res = tamper_frozen(&g.freeze(), |v| *field(v, "cell_size") = Value::Integer(0));
+    assert_frozen_corrupted(res, CorruptedGrid::InvalidCellSize(0));
+}